@@ -3,7 +3,7 @@
 //! flags you'd like to pass, or create `./config.ini` to test out the
 //! config file.
 
-#![allow(dead_code)]
+#![allow(dead_code, clippy::derivable_impls)]
 
 use structconf::{clap, StructConf};
 use strum_macros::{Display, EnumString};
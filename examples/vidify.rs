@@ -27,7 +27,8 @@ pub enum Player {
 
 use structconf::{clap, Error, StructConf};
 
-#[derive(Debug, StructConf)]
+// `Debug` is implemented by `StructConf`, since it has secrets.
+#[derive(StructConf)]
 pub struct Config {
     #[conf(help = "Display debug messages")]
     debug: bool,
@@ -88,6 +89,7 @@ pub struct Config {
 
     #[conf(
         no_short,
+        secret,
         help = "The client secret for the Spotify Web API. Check the install \
            guide to learn how to obtain yours",
         section = "SpotifyWeb"
@@ -101,7 +103,7 @@ pub struct Config {
     )]
     redirect_uri: String,
 
-    #[conf(no_short, no_long, section = "SpotifyWeb")]
+    #[conf(no_short, no_long, secret, section = "SpotifyWeb")]
     refresh_token: Option<String>,
 }
 
@@ -0,0 +1,145 @@
+//! The context in which a StructConf-derived struct is parsed. It's used by
+//! the derived code so that structs can be nested into others, in which case
+//! their arguments are prefixed and their options may be moved to a
+//! different section.
+
+use std::borrow::Cow;
+
+/// Not part of the public API; used by the derived code.
+#[doc(hidden)]
+#[derive(Clone, Debug)]
+pub struct Context {
+    /// Prefix for the argument identifiers, which must be unique in clap.
+    id_prefix: String,
+    /// Prefix for the long arguments.
+    long_prefix: String,
+    /// Section overriding the one of each option in the config file.
+    section: Option<String>,
+    /// Short arguments are disabled for nested structs to avoid conflicts.
+    shorts: bool,
+    /// The subcommands the struct is in, from the outermost one.
+    subcommands: Vec<String>,
+}
+
+impl Context {
+    /// The context of the struct that's being parsed directly.
+    pub fn root() -> Context {
+        Context {
+            id_prefix: String::new(),
+            long_prefix: String::new(),
+            section: None,
+            shorts: true,
+            subcommands: Vec::new(),
+        }
+    }
+
+    /// The context of a struct nested into the current one in the field
+    /// `field`, with `long` as the prefix for its long arguments and
+    /// `section` as its section in the config file.
+    pub fn nested(&self, field: &str, long: &str, section: &str) -> Context {
+        Context {
+            id_prefix: format!("{}{}.", self.id_prefix, field),
+            long_prefix: format!("{}{}-", self.long_prefix, long.replace('_', "-")),
+            section: Some(match &self.section {
+                Some(parent) => format!("{}.{}", parent, section),
+                None => section.to_string(),
+            }),
+            shorts: false,
+            subcommands: self.subcommands.clone(),
+        }
+    }
+
+    /// The context of a subcommand named `name`, with `section` as its
+    /// section in the config file. Its arguments are in their own parser, so
+    /// they aren't prefixed, but their identifiers are to tell them apart
+    /// from the rest of options.
+    pub fn subcommand(&self, name: &str, section: &str) -> Context {
+        Context {
+            id_prefix: format!("{}{}.", self.id_prefix, name),
+            long_prefix: String::new(),
+            section: Some(match &self.section {
+                Some(parent) => format!("{}.{}", parent, section),
+                None => section.to_string(),
+            }),
+            shorts: true,
+            subcommands: self
+                .subcommands
+                .iter()
+                .cloned()
+                .chain(std::iter::once(name.to_string()))
+                .collect(),
+        }
+    }
+
+    /// The subcommands the options are in, which are only read when all of
+    /// them are used.
+    pub fn subcommands(&self) -> Vec<String> {
+        self.subcommands.clone()
+    }
+
+    /// The identifier of an argument, used to obtain its value.
+    pub fn id<'a>(&self, id: &'a str) -> Cow<'a, str> {
+        if self.id_prefix.is_empty() {
+            Cow::Borrowed(id)
+        } else {
+            Cow::Owned(format!("{}{}", self.id_prefix, id))
+        }
+    }
+
+    /// The identifier of an argument, used to declare it. clap requires
+    /// it to live as long as the argument, so it has to be leaked for nested
+    /// structs. This only happens once per argument when the arguments are
+    /// parsed.
+    pub fn arg_id(&self, id: &'static str) -> &'static str {
+        leak(self.id(id))
+    }
+
+    /// The long name of an argument, used to declare it.
+    pub fn arg_long(&self, long: &'static str) -> &'static str {
+        if self.long_prefix.is_empty() {
+            long
+        } else {
+            leak(Cow::Owned(format!("{}{}", self.long_prefix, long)))
+        }
+    }
+
+    /// The short name of an argument, if available.
+    pub fn arg_short(&self, short: char) -> Option<char> {
+        if self.shorts {
+            Some(short)
+        } else {
+            None
+        }
+    }
+
+    /// The name of an argument as written in the command line, used to
+    /// report where a value was obtained from.
+    pub fn arg_name(&self, long: Option<&str>, short: Option<char>) -> String {
+        match (long, short.and_then(|short| self.arg_short(short))) {
+            (Some(long), _) => format!("--{}{}", self.long_prefix, long),
+            (None, Some(short)) => format!("-{}", short),
+            (None, None) => String::new(),
+        }
+    }
+
+    /// The section an option is in, which may be overriden by the context.
+    pub fn section<'a>(&'a self, section: &'a str) -> &'a str {
+        self.section.as_deref().unwrap_or(section)
+    }
+
+    /// The section a map is in. Since maps take the whole section, it's
+    /// placed inside the one overriden by the context instead.
+    pub fn subsection<'a>(&self, section: &'a str) -> Cow<'a, str> {
+        match &self.section {
+            Some(parent) => Cow::Owned(format!("{}.{}", parent, section)),
+            None => Cow::Borrowed(section),
+        }
+    }
+}
+
+fn leak(s: Cow<'static, str>) -> &'static str {
+    match s {
+        Cow::Borrowed(s) => s,
+        Cow::Owned(s) => Box::leak(s.into_boxed_str()),
+    }
+}
@@ -0,0 +1,106 @@
+//! The notices StructConf may emit while parsing, like when the config file
+//! is created. By default, they're printed to stderr, but they can be
+//! routed anywhere else with [`set_hook`]:
+//!
+//! ```rust
+//! use structconf::diagnostics;
+//!
+//! // Ignoring all the notices
+//! diagnostics::set_hook(|_| {});
+//! ```
+
+use crate::{Source, UnknownKey};
+
+use std::fmt;
+use std::sync::RwLock;
+
+/// A notice emitted while parsing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Diagnostic {
+    /// The config file was missing, so it was created.
+    CreatedFile { path: String },
+    /// The config file at `path` was missing, but it was created empty
+    /// instead of with a template, which is only available for INI files.
+    NoTemplate { path: String },
+    /// The value of `field` was obtained from a deprecated `source`, like a
+    /// former name of the option. The `deprecated` attribute gives it a
+    /// `note`, like what should be used instead.
+    Deprecated {
+        field: String,
+        source: Source,
+        note: Option<String>,
+    },
+    /// The config file at `path` was migrated from the version `from` to
+    /// the version `to` of the struct.
+    Migrated { path: String, from: u32, to: u32 },
+    /// The migrated config file at `path` couldn't be written back with the
+    /// `rewrite_migrated` attribute, so it will be migrated again the next
+    /// time it's loaded.
+    RewriteFailed { path: String, reason: String },
+    /// A key or section in the config file doesn't belong to any option,
+    /// with the `warn_unknown` attribute.
+    Unknown(UnknownKey),
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Diagnostic::CreatedFile { path } => write!(f, "Created config file at {}", path),
+            Diagnostic::NoTemplate { path } => write!(
+                f,
+                "Templates are only available for INI config files, so the one at {} \
+                 is created empty",
+                path
+            ),
+            Diagnostic::Deprecated {
+                field,
+                source,
+                note: Some(note),
+            } => write!(f, "The {} for `{}` is deprecated: {}", source, field, note),
+            Diagnostic::Deprecated {
+                field,
+                source,
+                note: None,
+            } => write!(f, "The {} for `{}` is deprecated", source, field),
+            Diagnostic::Migrated { path, from, to } => write!(
+                f,
+                "Migrated config file at {} from version {} to {}",
+                path, from, to
+            ),
+            Diagnostic::RewriteFailed { path, reason } => write!(
+                f,
+                "Couldn't rewrite the migrated config file at {}: {}",
+                path, reason
+            ),
+            Diagnostic::Unknown(unknown) => write!(f, "{}", unknown),
+        }
+    }
+}
+
+type Hook = Box<dyn Fn(&Diagnostic) + Send + Sync>;
+
+static HOOK: RwLock<Option<Hook>> = RwLock::new(None);
+
+/// Sets the function the notices will be passed to, replacing the previous
+/// one.
+pub fn set_hook<F>(hook: F)
+where
+    F: Fn(&Diagnostic) + Send + Sync + 'static,
+{
+    *HOOK.write().unwrap() = Some(Box::new(hook));
+}
+
+/// Restores the default behaviour, which prints the notices to stderr.
+pub fn reset_hook() {
+    *HOOK.write().unwrap() = None;
+}
+
+/// Not part of the public API; used by the derived code to emit a notice.
+#[doc(hidden)]
+pub fn emit(diagnostic: Diagnostic) {
+    match &*HOOK.read().unwrap() {
+        Some(hook) => hook(&diagnostic),
+        None => eprintln!("{}", diagnostic),
+    }
+}
@@ -0,0 +1,623 @@
+//! The file formats available for the config file. INI is always supported,
+//! and TOML, JSON and YAML are available with the `toml`, `json` and `yaml`
+//! cargo features, respectively.
+//!
+//! Every format is converted from and into an [`ini::Ini`](
+//! https://docs.rs/rust-ini/latest/ini/struct.Ini.html) document, which is
+//! what the derived code works with. Sections are mapped to tables (or
+//! objects), and sections with dots in their name, like `server.db`, are
+//! mapped to nested tables. Lists are mapped to repeated keys.
+
+use crate::{write, Error};
+
+use ini::Ini;
+use std::fs;
+use std::path::Path;
+#[cfg(any(feature = "toml", feature = "json", feature = "yaml"))]
+use tree::{Node, Scalar};
+
+/// The names of the formats that may be used, either with the `format`
+/// attribute or as the extension of the config file.
+pub const NAMES: &[&str] = &["ini", "toml", "json", "yaml", "yml"];
+
+/// A format the config file may be written in.
+pub trait FileFormat {
+    /// Parses the contents of a config file into a document.
+    fn parse(&self, contents: &str) -> Result<Ini, Error>;
+
+    /// Serializes a document into the contents of a config file.
+    fn serialize(&self, doc: &Ini) -> Result<String, Error>;
+}
+
+/// The default format, with the same syntax `rust-ini` uses.
+pub struct IniFormat;
+
+impl FileFormat for IniFormat {
+    fn parse(&self, contents: &str) -> Result<Ini, Error> {
+        Ini::load_from_str(contents).map_err(Error::Ini)
+    }
+
+    fn serialize(&self, doc: &Ini) -> Result<String, Error> {
+        let mut buf = Vec::new();
+        doc.write_to(&mut buf)?;
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+}
+
+/// The TOML format, available with the `toml` feature.
+#[cfg(feature = "toml")]
+pub struct TomlFormat;
+
+#[cfg(feature = "toml")]
+impl FileFormat for TomlFormat {
+    fn parse(&self, contents: &str) -> Result<Ini, Error> {
+        use toml::Value;
+
+        fn to_node(value: Value) -> Result<Option<Node>, Error> {
+            Ok(Some(match value {
+                Value::String(s) => Node::Scalar(s),
+                Value::Table(table) => {
+                    let mut entries = Vec::new();
+                    for (key, val) in table {
+                        if let Some(node) = to_node(val)? {
+                            entries.push((key, node));
+                        }
+                    }
+                    Node::Table(entries)
+                }
+                Value::Array(values) => {
+                    let mut list = Vec::new();
+                    for val in values {
+                        match to_node(val)? {
+                            Some(Node::Scalar(s)) => list.push(s),
+                            Some(_) => return Err(Node::nested_list_err()),
+                            None => {}
+                        }
+                    }
+                    Node::List(list)
+                }
+                other => Node::Scalar(other.to_string()),
+            }))
+        }
+
+        let value = contents
+            .parse::<Value>()
+            .map_err(|e| Error::Format(e.to_string()))?;
+        to_node(value)?.unwrap().into_ini()
+    }
+
+    fn serialize(&self, doc: &Ini) -> Result<String, Error> {
+        use toml::{map::Map, Value};
+
+        fn from_node(node: Node) -> Value {
+            match node {
+                Node::Scalar(s) => match Scalar::guess(&s) {
+                    Scalar::Bool(b) => Value::Boolean(b),
+                    Scalar::Int(i) => Value::Integer(i),
+                    Scalar::Float(f) => Value::Float(f),
+                    Scalar::Str => Value::String(s),
+                },
+                Node::List(list) => {
+                    Value::Array(list.into_iter().map(Node::Scalar).map(from_node).collect())
+                }
+                Node::Table(entries) => {
+                    let mut table = Map::new();
+                    for (key, val) in entries {
+                        table.insert(key, from_node(val));
+                    }
+                    Value::Table(table)
+                }
+            }
+        }
+
+        let node = Node::from_ini(doc);
+        toml::to_string(&from_node(node)).map_err(|e| Error::Format(e.to_string()))
+    }
+}
+
+/// The JSON format, available with the `json` feature.
+#[cfg(feature = "json")]
+pub struct JsonFormat;
+
+#[cfg(feature = "json")]
+impl FileFormat for JsonFormat {
+    fn parse(&self, contents: &str) -> Result<Ini, Error> {
+        use serde_json::Value;
+
+        fn to_node(value: Value) -> Result<Option<Node>, Error> {
+            Ok(Some(match value {
+                Value::Null => return Ok(None),
+                Value::String(s) => Node::Scalar(s),
+                Value::Object(object) => {
+                    let mut entries = Vec::new();
+                    for (key, val) in object {
+                        if let Some(node) = to_node(val)? {
+                            entries.push((key, node));
+                        }
+                    }
+                    Node::Table(entries)
+                }
+                Value::Array(values) => {
+                    let mut list = Vec::new();
+                    for val in values {
+                        match to_node(val)? {
+                            Some(Node::Scalar(s)) => list.push(s),
+                            Some(_) => return Err(Node::nested_list_err()),
+                            None => {}
+                        }
+                    }
+                    Node::List(list)
+                }
+                other => Node::Scalar(other.to_string()),
+            }))
+        }
+
+        let value =
+            serde_json::from_str::<Value>(contents).map_err(|e| Error::Format(e.to_string()))?;
+        match to_node(value)? {
+            Some(node) => node.into_ini(),
+            None => Ok(Ini::new()),
+        }
+    }
+
+    fn serialize(&self, doc: &Ini) -> Result<String, Error> {
+        use serde_json::{Map, Number, Value};
+
+        fn from_node(node: Node) -> Value {
+            match node {
+                Node::Scalar(s) => match Scalar::guess(&s) {
+                    Scalar::Bool(b) => Value::Bool(b),
+                    Scalar::Int(i) => Value::Number(i.into()),
+                    Scalar::Float(f) => Number::from_f64(f).map_or(Value::String(s), Value::Number),
+                    Scalar::Str => Value::String(s),
+                },
+                Node::List(list) => {
+                    Value::Array(list.into_iter().map(Node::Scalar).map(from_node).collect())
+                }
+                Node::Table(entries) => {
+                    let mut object = Map::new();
+                    for (key, val) in entries {
+                        object.insert(key, from_node(val));
+                    }
+                    Value::Object(object)
+                }
+            }
+        }
+
+        let node = Node::from_ini(doc);
+        serde_json::to_string_pretty(&from_node(node)).map_err(|e| Error::Format(e.to_string()))
+    }
+}
+
+/// The YAML format, available with the `yaml` feature.
+#[cfg(feature = "yaml")]
+pub struct YamlFormat;
+
+#[cfg(feature = "yaml")]
+impl FileFormat for YamlFormat {
+    fn parse(&self, contents: &str) -> Result<Ini, Error> {
+        use serde_yaml::Value;
+
+        fn to_string(value: Value) -> Result<Option<String>, Error> {
+            match value {
+                Value::Null => Ok(None),
+                Value::String(s) => Ok(Some(s)),
+                Value::Bool(b) => Ok(Some(b.to_string())),
+                Value::Number(n) => Ok(Some(n.to_string())),
+                _ => Err(Error::Format("keys must be scalar values".to_string())),
+            }
+        }
+
+        fn to_node(value: Value) -> Result<Option<Node>, Error> {
+            Ok(Some(match value {
+                Value::Mapping(mapping) => {
+                    let mut entries = Vec::new();
+                    for (key, val) in mapping {
+                        let key = match to_string(key)? {
+                            Some(key) => key,
+                            None => continue,
+                        };
+                        if let Some(node) = to_node(val)? {
+                            entries.push((key, node));
+                        }
+                    }
+                    Node::Table(entries)
+                }
+                Value::Sequence(values) => {
+                    let mut list = Vec::new();
+                    for val in values {
+                        match to_node(val)? {
+                            Some(Node::Scalar(s)) => list.push(s),
+                            Some(_) => return Err(Node::nested_list_err()),
+                            None => {}
+                        }
+                    }
+                    Node::List(list)
+                }
+                Value::Tagged(tagged) => return to_node(tagged.value),
+                other => match to_string(other)? {
+                    Some(s) => Node::Scalar(s),
+                    None => return Ok(None),
+                },
+            }))
+        }
+
+        let value =
+            serde_yaml::from_str::<Value>(contents).map_err(|e| Error::Format(e.to_string()))?;
+        match to_node(value)? {
+            Some(node) => node.into_ini(),
+            None => Ok(Ini::new()),
+        }
+    }
+
+    fn serialize(&self, doc: &Ini) -> Result<String, Error> {
+        use serde_yaml::{Mapping, Value};
+
+        fn from_node(node: Node) -> Value {
+            match node {
+                Node::Scalar(s) => match Scalar::guess(&s) {
+                    Scalar::Bool(b) => Value::Bool(b),
+                    Scalar::Int(i) => Value::Number(i.into()),
+                    Scalar::Float(f) => Value::Number(f.into()),
+                    Scalar::Str => Value::String(s),
+                },
+                Node::List(list) => {
+                    Value::Sequence(list.into_iter().map(Node::Scalar).map(from_node).collect())
+                }
+                Node::Table(entries) => {
+                    let mut mapping = Mapping::new();
+                    for (key, val) in entries {
+                        mapping.insert(Value::String(key), from_node(val));
+                    }
+                    Value::Mapping(mapping)
+                }
+            }
+        }
+
+        let node = Node::from_ini(doc);
+        serde_yaml::to_string(&from_node(node)).map_err(|e| Error::Format(e.to_string()))
+    }
+}
+
+/// Obtains a format from its name, which must be one of `NAMES`. This
+/// fails in case the feature required for the format isn't enabled.
+pub fn from_name(name: &str) -> Result<Box<dyn FileFormat>, Error> {
+    #[cfg(not(all(feature = "toml", feature = "json", feature = "yaml")))]
+    let disabled = |feature: &str| {
+        Err(Error::Format(format!(
+            "the `{}` format requires the `{}` feature in structconf",
+            name, feature
+        )))
+    };
+
+    match name {
+        "ini" => Ok(Box::new(IniFormat)),
+        #[cfg(feature = "toml")]
+        "toml" => Ok(Box::new(TomlFormat)),
+        #[cfg(not(feature = "toml"))]
+        "toml" => disabled("toml"),
+        #[cfg(feature = "json")]
+        "json" => Ok(Box::new(JsonFormat)),
+        #[cfg(not(feature = "json"))]
+        "json" => disabled("json"),
+        #[cfg(feature = "yaml")]
+        "yaml" | "yml" => Ok(Box::new(YamlFormat)),
+        #[cfg(not(feature = "yaml"))]
+        "yaml" | "yml" => disabled("yaml"),
+        _ => Err(Error::Format(format!("unknown format `{}`", name))),
+    }
+}
+
+/// Obtains the format for a config file from its extension. Unknown
+/// extensions fall back to INI.
+pub fn from_path(path: &Path) -> Result<Box<dyn FileFormat>, Error> {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase());
+    match ext {
+        Some(ext) if NAMES.contains(&ext.as_str()) => from_name(&ext),
+        _ => Ok(Box::new(IniFormat)),
+    }
+}
+
+/// Obtains the format with the given name, or from the file's extension if
+/// no name is provided.
+fn choose(path: &Path, name: Option<&str>) -> Result<Box<dyn FileFormat>, Error> {
+    match name {
+        Some(name) => from_name(name),
+        None => from_path(path),
+    }
+}
+
+/// Not part of the public API; used by the derived code to know whether
+/// the config file at `path` is in the INI format, given the same
+/// parameters as `choose`.
+#[doc(hidden)]
+pub fn is_ini(path: &Path, name: Option<&str>) -> bool {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase());
+    match (name, ext) {
+        (Some(name), _) => name == "ini",
+        (None, Some(ext)) => ext == "ini" || !NAMES.contains(&ext.as_str()),
+        (None, None) => true,
+    }
+}
+
+/// Loads the config file at `path`, in the format with the given name or
+/// the one obtained from its extension. Empty files are valid in every
+/// format.
+pub fn load<P: AsRef<Path>>(path: P, name: Option<&str>) -> Result<Ini, Error> {
+    let path = path.as_ref();
+    let format = choose(path, name)?;
+    let contents = fs::read_to_string(path)?;
+    let contents = contents.trim_start_matches('\u{feff}');
+    if contents.trim().is_empty() {
+        return Ok(Ini::new());
+    }
+
+    format.parse(contents)
+}
+
+/// Writes the document into the config file at `path`, in the format with
+/// the given name or the one obtained from its extension.
+/// It's written atomically, keeping the permissions of the existing file.
+pub fn save<P: AsRef<Path>>(doc: &Ini, path: P, name: Option<&str>) -> Result<(), Error> {
+    save_with_mode(doc, path.as_ref(), name, None)
+}
+
+/// Same as `save`, but setting the permissions of the file to `mode`.
+pub(crate) fn save_with_mode(
+    doc: &Ini,
+    path: &Path,
+    name: Option<&str>,
+    mode: Option<u32>,
+) -> Result<(), Error> {
+    let format = choose(path, name)?;
+    write::atomic_write(path, format.serialize(doc)?.as_bytes(), mode)?;
+
+    Ok(())
+}
+
+/// Merges the document of a config file into `doc`, replacing the keys in
+/// `doc` with the ones in `layer`. Repeated keys, which represent lists, are
+/// replaced as a whole. This is used to read multiple layers of config
+/// files, like a system-wide one and a user one.
+pub fn merge(doc: &mut Ini, layer: &Ini) {
+    for (section, props) in layer.iter() {
+        let merged = doc
+            .entry(section.map(str::to_string))
+            .or_insert_with(Default::default);
+        let mut replaced = Vec::new();
+        for (key, val) in props.iter() {
+            if !replaced.contains(&key) {
+                merged.remove_all(key).for_each(drop);
+                replaced.push(key);
+            }
+            merged.append(key, val);
+        }
+    }
+}
+
+/// Not part of the public API; used by the derived code to join the values
+/// of a list with a delimiter. The delimiters and backslashes inside the
+/// values are escaped with a backslash, so that they can be split again.
+#[doc(hidden)]
+pub fn join_list(vals: &[String], delimiter: char) -> String {
+    let mut joined = String::new();
+    for (i, val) in vals.iter().enumerate() {
+        if i > 0 {
+            joined.push(delimiter);
+        }
+        for c in val.chars() {
+            if c == delimiter || c == '\\' {
+                joined.push('\\');
+            }
+            joined.push(c);
+        }
+    }
+
+    joined
+}
+
+/// Not part of the public API; used by the derived code to split a list
+/// joined with `join_list`. Each value is trimmed, and backslashes that
+/// don't escape anything are kept as they are.
+#[doc(hidden)]
+pub fn split_list(joined: &str, delimiter: char) -> Vec<String> {
+    let mut vals = Vec::new();
+    let mut val = String::new();
+    let mut chars = joined.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&next)) if next == delimiter || next == '\\' => {
+                val.push(next);
+                chars.next();
+            }
+            (c, _) if c == delimiter => vals.push(std::mem::take(&mut val)),
+            (c, _) => val.push(c),
+        }
+    }
+    vals.push(val);
+
+    vals.into_iter().map(|val| val.trim().to_string()).collect()
+}
+
+/// Finds the line number of a key in the contents of a config file, if
+/// possible. Sections are detected with the INI and TOML syntax,
+/// `[section]`. If there are none, the key is searched in the entire file,
+/// which works for simple JSON and YAML files.
+pub(crate) fn find_line(contents: &str, section: &str, key: &str) -> Option<usize> {
+    let is_header = |line: &str| line.starts_with('[') && line.ends_with(']');
+    let has_headers = contents.lines().any(|line| is_header(line.trim()));
+    let is_key = |line: &str| {
+        let line = line.trim_start_matches(['"', '\'']);
+        match line.strip_prefix(key) {
+            Some(rest) => rest
+                .trim_start_matches(['"', '\''])
+                .trim_start()
+                .starts_with(['=', ':']),
+            None => false,
+        }
+    };
+
+    let mut in_section = !has_headers;
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if is_header(line) {
+            in_section = line[1..line.len() - 1].trim() == section;
+        } else if in_section && is_key(line) {
+            return Some(i + 1);
+        }
+    }
+
+    None
+}
+
+/// Helpers for the formats with nested structures and types.
+#[cfg(any(feature = "toml", feature = "json", feature = "yaml"))]
+mod tree {
+    use crate::Error;
+
+    use ini::Ini;
+
+    /// The intermediate tree the formats with nested structures are converted
+    /// from and into, so that the conversion to and from `Ini` is shared.
+    pub enum Node {
+        Scalar(String),
+        List(Vec<String>),
+        Table(Vec<(String, Node)>),
+    }
+
+    impl Node {
+        pub fn nested_list_err() -> Error {
+            Error::Format("lists may only contain values".to_string())
+        }
+
+        /// Converts the top-level table into a document. Its values are stored
+        /// in the general section, and its tables become sections.
+        pub fn into_ini(self) -> Result<Ini, Error> {
+            fn insert_section(doc: &mut Ini, section: Option<&str>, entries: Vec<(String, Node)>) {
+                // The section is created even if it's empty.
+                doc.entry(section.map(String::from))
+                    .or_insert_with(Default::default);
+                for (key, val) in entries {
+                    match val {
+                        Node::Scalar(s) => {
+                            doc.with_section(section).set(key, s);
+                        }
+                        Node::List(list) => {
+                            let props = doc
+                                .entry(section.map(String::from))
+                                .or_insert_with(Default::default);
+                            for s in list {
+                                props.append(key.clone(), s);
+                            }
+                        }
+                        Node::Table(entries) => {
+                            let name = match section {
+                                Some(section) => format!("{}.{}", section, key),
+                                None => key,
+                            };
+                            insert_section(doc, Some(&name), entries);
+                        }
+                    }
+                }
+            }
+
+            let entries = match self {
+                Node::Table(entries) => entries,
+                _ => {
+                    return Err(Error::Format(
+                        "expected a table at the top level".to_string(),
+                    ))
+                }
+            };
+            let mut doc = Ini::new();
+            insert_section(&mut doc, None, entries);
+            Ok(doc)
+        }
+
+        /// Converts a document into a table, where sections with dots in their
+        /// name become nested tables.
+        pub fn from_ini(doc: &Ini) -> Node {
+            fn find_table<'a>(
+                entries: &'a mut Vec<(String, Node)>,
+                path: &[&str],
+            ) -> &'a mut Vec<(String, Node)> {
+                let (first, rest) = match path.split_first() {
+                    Some(split) => split,
+                    None => return entries,
+                };
+                let pos = match entries.iter().position(|(key, _)| key == first) {
+                    Some(pos) => pos,
+                    None => {
+                        entries.push((first.to_string(), Node::Table(Vec::new())));
+                        entries.len() - 1
+                    }
+                };
+                // A value with the same name as the table is replaced.
+                if !matches!(entries[pos].1, Node::Table(_)) {
+                    entries[pos].1 = Node::Table(Vec::new());
+                }
+                match &mut entries[pos].1 {
+                    Node::Table(inner) => find_table(inner, rest),
+                    _ => unreachable!(),
+                }
+            }
+
+            let mut root = Vec::new();
+            for (section, props) in doc.iter() {
+                let path = section.map(|s| s.split('.').collect::<Vec<_>>());
+                let table = find_table(&mut root, path.as_deref().unwrap_or(&[]));
+                for (key, val) in props.iter() {
+                    match table.iter_mut().find(|(k, _)| k == key) {
+                        // Repeated keys are grouped into a list.
+                        Some((_, node)) => match node {
+                            Node::List(list) => list.push(val.to_string()),
+                            Node::Scalar(prev) => {
+                                *node = Node::List(vec![prev.clone(), val.to_string()]);
+                            }
+                            Node::Table(_) => {}
+                        },
+                        None => table.push((key.to_string(), Node::Scalar(val.to_string()))),
+                    }
+                }
+            }
+
+            Node::Table(root)
+        }
+    }
+
+    /// A value guessed from its string representation, so that the formats with
+    /// types don't store every value as a string. The guess must convert back
+    /// to the exact same string, so that no information is lost.
+    pub enum Scalar {
+        Bool(bool),
+        Int(i64),
+        Float(f64),
+        Str,
+    }
+
+    impl Scalar {
+        pub fn guess(s: &str) -> Scalar {
+            if let Ok(b) = s.parse::<bool>() {
+                return Scalar::Bool(b);
+            }
+            if let Ok(i) = s.parse::<i64>() {
+                if i.to_string() == s {
+                    return Scalar::Int(i);
+                }
+            }
+            if let Ok(f) = s.parse::<f64>() {
+                if f.is_finite() && f.to_string() == s {
+                    return Scalar::Float(f);
+                }
+            }
+
+            Scalar::Str
+        }
+    }
+}
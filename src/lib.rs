@@ -30,30 +30,160 @@
 //! `structconf::ini` to avoid duplicate dependencies and not having to include
 //! them in your `Cargo.toml`.
 //!
+//! The config file is INI by default, but TOML, JSON and YAML are also
+//! available with the `toml`, `json` and `yaml` cargo features. The format
+//! is chosen from the file's extension, or with the `format` attribute. See
+//! the [`format`](format/index.html) module for more details.
+//!
+//! Multiple layers of config files can be read with
+//! [`parse_layers`](trait.StructConf.html#tymethod.parse_layers), like a
+//! system-wide one, a user one and a project one. The keys in the later
+//! files replace the ones in the earlier files, and missing files are
+//! skipped:
+//!
+//! ```rust
+//! use structconf::{clap, StructConf};
+//!
+//! #[derive(StructConf)]
+//! struct Config {
+//!     timeout: i32,
+//! }
+//!
+//! let app = clap::App::new("demo");
+//! let layers = ["/etc/demo.ini", "demo.ini"];
+//! let conf = Config::parse_layers(app, &layers).unwrap();
+//! ```
+//!
+//! The source each value was obtained from can be inspected with
+//! [`parse_with_sources`](trait.StructConf.html#tymethod.parse_with_sources),
+//! which is useful to explain why a field has a specific value:
+//!
+//! ```rust
+//! use structconf::{clap, StructConf};
+//!
+//! #[derive(StructConf)]
+//! struct Config {
+//!     #[conf(default = "30")]
+//!     timeout: i32,
+//! }
+//!
+//! let app = clap::App::new("demo");
+//! let (conf, sources) = Config::parse_with_sources(app, "config.ini").unwrap();
+//! // "`timeout` was obtained from the default value"
+//! println!("{}", sources.explain("timeout").unwrap());
+//! ```
+//!
+//! Generic structs are supported as well. The type parameters used in the
+//! fields are bounded by `FromStr + Display + Default`, or by `StructConf`
+//! for nested structs, and `PhantomData` markers are skipped. When a type
+//! doesn't satisfy them, the error points to the field using it:
+//!
+//! ```rust
+//! use std::marker::PhantomData;
+//! use structconf::StructConf;
+//!
+//! #[derive(StructConf)]
+//! struct Config<T, B> {
+//!     timeout: T,
+//!     backend: PhantomData<B>,
+//! }
+//! ```
+//!
 //! Additional attributes can be added to its fields to customize how they
 //! are parsed:
 //!
 //! ## General attributes
 //! * `default = "..."`: a Rust expression that will be evaluated as a
-//! fallback value. For example, `default = "1+2"`, or
-//! `default = "String::from(\"hello\"")`. Otherwise, the value given by
-//! [`std::default::Default`](
-//! https://doc.rust-lang.org/std/default/trait.Default.html) will be used,
-//! or in case the assigned type is `Option<T>`\*, `None`.
+//!   fallback value. For example, `default = "1+2"`, or
+//!   `default = "String::from(\"hello\"")`. Otherwise, the value given by
+//!   [`std::default::Default`](
+//!   https://doc.rust-lang.org/std/default/trait.Default.html) will be used,
+//!   or in case the assigned type is `Option<T>`\*, `None`.
+//!
+//! * `delimiter = ","`: for lists, the character used to separate multiple
+//!   values in a single argument or config file option. It can be escaped
+//!   inside a value with a backslash, like `--names 'a\,b'`. INI files
+//!   unescape backslashes themselves when they're read, so it has to be
+//!   doubled in them, like `names = a\\,b`, which is done automatically
+//!   when the file is written.
+//! * `required`: the value must be set in the arguments, the environment or
+//!   the config file. Otherwise, instead of falling back to a default value,
+//!   [`Error::Missing`](enum.Error.html#variant.Missing) is returned, which
+//!   lists where it can be set. It's useful for values without a sensible
+//!   default, like API keys, so their type doesn't need to implement
+//!   `Default`.
 //!
 //! \* *Note: the assigned type must be exactly `Option<T>` for this to work.
-//! `std::option::Option<T>` won't work, for example.*
+//! `std::option::Option<T>` won't work, for example. The same applies to
+//! lists and maps.*
+//!
+//! Fields of type `Vec<T>`\* are lists of values. In the argument parser,
+//! they can be passed multiple times, like `--include a --include b`. In the
+//! config file, their key can be repeated, or it can be a list in formats
+//! like TOML. With a `delimiter`, multiple values may also be written at
+//! once, like `--include a,b` or `include = a,b`. Environment variables are
+//! always split by the delimiter, or by commas if there isn't one.
+//!
+//! Fields of type `HashMap<K, V>` or `BTreeMap<K, V>`\* are maps. In the
+//! config file, they take every key in their own section, which must be set
+//! with `section_map`. In the argument parser, entries are passed as
+//! `key=value`, and they may be repeated, like `--alias ll="ls -l"`. The
+//! entries in the arguments are merged with the ones in the config file,
+//! replacing them if their key is repeated. Maps aren't available as
+//! environment variables.
+//!
+//! ## Validation attributes
+//! The values are validated after being parsed, whichever source they came
+//! from. If any of these checks fail, [`Error::Validation`](
+//! enum.Error.html#variant.Validation) is returned with the field, the
+//! source it came from, its raw value and the reason. The default values
+//! aren't validated. For lists and maps, each of their values is validated.
+//!
+//! * `range = "..."`: a Rust range expression the value must be in, like
+//!   `range = "1..=65535"` or `range = "0.0.."`.
+//! * `regex = "..."`: a regular expression the entire raw value must match.
+//!   It requires the `regex` cargo feature, which is enabled by default;
+//!   otherwise, using it is a compile error. The pattern is checked when
+//!   the macro is expanded, and only compiled once at runtime.
+//! * `non_empty`: the raw value can't be empty or only whitespace.
+//! * `validate = "path::to::fn"`: a custom function that takes a reference to
+//!   the parsed value and returns `Result<(), E>`, where `E` implements
+//!   `Display` and is used as the reason.
+//!
+//! ```rust
+//! use structconf::StructConf;
+//!
+//! fn is_even(val: &u32) -> Result<(), String> {
+//!     if val % 2 == 0 {
+//!         Ok(())
+//!     } else {
+//!         Err("the value must be even".to_string())
+//!     }
+//! }
+//!
+//! # #[cfg(feature = "regex")]
+//! #[derive(StructConf)]
+//! struct Server {
+//!     #[conf(range = "1..=65535", default = "8080")]
+//!     port: u16,
+//!     #[conf(non_empty, regex = "[a-z.]+")]
+//!     host: String,
+//!     #[conf(validate = "is_even")]
+//!     workers: u32,
+//! }
+//! ```
 //!
 //! ## Argument parser attributes
 //! * `help = "..."`: the help message shown in the argument parser when
-//! `--help` is used.
+//!   `--help` is used. It's also written as a comment in the templates for
+//!   the config file.
 //! * `long = "arg_name"`: a custom long argument name. Otherwise, it will be
-//! obtained directly from the field's name. `do_something` will be
-//! `--do-something`.
+//!   obtained directly from the field's name. `do_something` will be
+//!   `--do-something`.
 //! * `no_long`: don't include the option as a long argument.
 //! * `short = "x"`: a custom short argument name (only made up of a single
-//! character). Otherwise, it will be obtained directly from the field's
-//! name. `do_something` will be `-d`.
+//!   character). Otherwise, it will be obtained directly from the field's
+//!   name. `do_something` will be `-d`.
 //! * `no_short`: don't include the option as a short argument.
 //! * `negated_arg`: the flag's value is the opposite:
 //!
@@ -68,30 +198,286 @@
 //! }
 //! ```
 //!
+//! * `possible_values = "..."`: an expression with a slice of the values
+//!   the argument may take, like `&["fast", "slow"]`, or `MyEnum::VARIANTS`
+//!   with `strum::VariantNames`. Any other value is rejected by the argument
+//!   parser, and they're offered in the shell completions. For lists with a
+//!   `delimiter`, each of the split values is checked instead, so they
+//!   aren't offered.
+//!
 //! If both `no_long` and `no_short` are provided, the option won't be
 //! available in the argument parser at all.
 //!
+//! Shell completion scripts can be generated with
+//! [`completions`](trait.StructConf.html#method.completions), which
+//! requires the `completions` cargo feature. Arguments of type `PathBuf`
+//! are completed as files.
+//!
 //! ## Config file attributes
 //! * `file = "..."`: set a custom name in the config file. Otherwise, it will
-//! be the same as the field's identifier.
+//!   be the same as the field's identifier.
 //! * `no_file`: don't include the option in the config file.
 //! * `section`: the section in the config file where the option will be.
-//! Otherwise, `Default` is used. For example,
-//! `#[structconf(section = "Planes")] model_id: i32` will look like this in
-//! the config file:
+//!   Otherwise, `Default` is used. For example,
+//!   `#[structconf(section = "Planes")] model_id: i32` will look like this in
+//!   the config file:
 //!
 //! ```ini
 //! [Planes]
 //! model_id = 123
 //! ```
+//!
+//! * `section_map = "..."`: for maps, the section in the config file whose
+//!   keys are all part of the map. It can't be used by any other option.
+//!   For example, `#[conf(section_map = "Aliases")] aliases: HashMap<String,
+//!   String>` will look like this in the config file:
+//!
+//! ```ini
+//! [Aliases]
+//! ll = ls -l
+//! gs = git status
+//! ```
+//!
+//! ## Environment variable attributes
+//! * `env = "..."`: the name of an environment variable the option can also
+//!   be read from. Its value is parsed the same way as in the config file.
+//! * `no_env`: don't read the option from the environment, even if the
+//!   struct has an `env_prefix`.
+//!
+//! ## Renamed options
+//! * `alias = "..."`: a former name of the option, which keeps working both
+//!   as a long argument, with its underscores replaced by dashes, and as a
+//!   key in the config file. It may be repeated for multiple names. The
+//!   current name takes priority, and it's the only one written or
+//!   documented.
+//! * `deprecated = "..."`: a note on what to do instead, like
+//!   `"use `timeout_ms` instead"`.
+//!
+//! Whenever an alias is used, or a `deprecated` option without aliases is
+//! given a value, a [`Diagnostic::Deprecated`](
+//! diagnostics/enum.Diagnostic.html#variant.Deprecated) is emitted.
+//!
+//! ```rust
+//! use structconf::StructConf;
+//!
+//! #[derive(StructConf)]
+//! struct Config {
+//!     // Also available as `--timeout` and `timeout`.
+//!     #[conf(no_short, alias = "timeout", deprecated = "use `timeout_ms` instead")]
+//!     timeout_ms: u64,
+//! }
+//! ```
+//!
+//! ## Secrets
+//! Fields with the `secret` attribute hold credentials like passwords or
+//! tokens:
+//!
+//! * Their value is redacted in the errors, in the documentation, and in the
+//!   `Debug` implementation generated for the struct, which mustn't derive
+//!   it.
+//! * They aren't written into the config file, keeping their value in the
+//!   existing one, unless [`WriteOptions::secrets`](
+//!   write/struct.WriteOptions.html#method.secrets) is used.
+//! * Their value can also be read from a file, like the ones mounted by
+//!   Docker or Kubernetes, with their argument suffixed by `-file`, their
+//!   environment variable by `_FILE`, or their key in the config file by
+//!   `_file`. The value itself takes priority over its file.
+//!
+//! ```rust
+//! use structconf::StructConf;
+//!
+//! #[derive(StructConf)]
+//! struct Config {
+//!     // Available as `--token` and `--token-file`, and as `token` and
+//!     // `token_file` in the config file.
+//!     #[conf(secret, no_short)]
+//!     token: Option<String>,
+//! }
+//! ```
+//!
+//! ## Nested structs
+//! A field whose type also derives `StructConf` can be embedded into the
+//! struct with one of these attributes:
+//!
+//! * `flatten`: its options are merged into the struct's, as if they were
+//!   declared in it. Since the conflicts between their names can only be
+//!   found at runtime, they're reported with
+//!   [`Error::Conflict`](enum.Error.html#variant.Conflict) when parsing, or
+//!   with a panic by `parse_args` and `parse_args_from`.
+//! * `nested`: its options are moved to their own section in the config
+//!   file, named after the field, and its long arguments are prefixed with
+//!   the field's name. Short arguments aren't available for nested structs.
+//!   The section and the prefix can be customized with `section = "..."`
+//!   and `long = "..."`, respectively. Environment variables aren't
+//!   prefixed, so the ones embedded more than once are also reported with
+//!   [`Error::Conflict`](enum.Error.html#variant.Conflict) when parsing.
+//!
+//! ```rust
+//! use structconf::StructConf;
+//!
+//! #[derive(StructConf)]
+//! struct DatabaseConfig {
+//!     port: u16,
+//! }
+//!
+//! #[derive(StructConf)]
+//! struct Config {
+//!     // Available as `--database-port`, and as `port` inside the
+//!     // `[database]` section.
+//!     #[conf(nested)]
+//!     database: DatabaseConfig,
+//!     // Available as `--backup-port`, and as `port` inside the `[Backup]`
+//!     // section.
+//!     #[conf(nested, long = "backup", section = "Backup")]
+//!     replica: DatabaseConfig,
+//! }
+//! ```
+//!
+//! ## Subcommands
+//! `StructConf` can also be derived for enums whose variants contain a
+//! single StructConf-derived struct. Each variant is a subcommand with the
+//! struct's arguments, and its options are in their own section in the
+//! config file. Only the section of the subcommand in use is read and
+//! written. The enum is embedded into a struct with the `subcommand`
+//! attribute, which is required unless it's wrapped by `Option`. The
+//! variants may have these attributes:
+//!
+//! * `name = "..."`: the name of the subcommand. Otherwise, it's the
+//!   variant's name in kebab case. `DryRun` will be `dry-run`.
+//! * `section = "..."`: the section in the config file. Otherwise, it's the
+//!   subcommand's name.
+//! * `help = "..."`: the help message of the subcommand.
+//!
+//! The [struct attributes](#struct-attributes) aren't available for enums;
+//! they're used in the struct that embeds them instead.
+//!
+//! ```rust
+//! use structconf::StructConf;
+//!
+//! #[derive(StructConf)]
+//! struct ServeConfig {
+//!     port: u16,
+//! }
+//!
+//! #[derive(StructConf)]
+//! struct MigrateConfig {
+//!     dry_run: bool,
+//! }
+//!
+//! #[derive(StructConf)]
+//! enum Command {
+//!     // Available as `myapp serve --port 80`, and as `port` inside the
+//!     // `[serve]` section.
+//!     #[conf(help = "Starts the server")]
+//!     Serve(ServeConfig),
+//!     #[conf(section = "Migrations")]
+//!     Migrate(MigrateConfig),
+//! }
+//!
+//! #[derive(StructConf)]
+//! struct Config {
+//!     // Shared by all the subcommands, as `myapp --verbose serve`.
+//!     verbose: bool,
+//!     #[conf(subcommand)]
+//!     command: Command,
+//! }
+//! ```
+//!
+//! ## Struct attributes
+//! These are written as `#[conf(...)]` on top of the struct, and they apply
+//! to all of its fields:
+//!
+//! * `env_prefix = "..."`: every field will be read from an environment
+//!   variable named after the prefix and the field's identifier in
+//!   uppercase, unless it has a custom `env` or `no_env`. For example,
+//!   with `env_prefix = "MYAPP_"`, `timeout` will be read from
+//!   `MYAPP_TIMEOUT`.
+//! * `format = "..."`: the format of the config file, which may be `ini`,
+//!   `toml`, `json` or `yaml`. Otherwise, it's obtained from the file's
+//!   extension, falling back to INI for unknown extensions.
+//! * `missing_file = "..."`: what to do when the config file doesn't exist
+//!   in `parse_file`:
+//!   * `create` (default): an empty file is created, and a
+//!     [`Diagnostic`](diagnostics/enum.Diagnostic.html) is emitted, which is
+//!     printed to stderr unless a different
+//!     [hook](diagnostics/fn.set_hook.html) is set.
+//!   * `template`: it's created with the template from
+//!     [`write_template`](trait.StructConf.html#tymethod.write_template), and
+//!     a `Diagnostic` is emitted as well. Templates are only available for
+//!     INI files, so the rest are created empty, with another `Diagnostic`
+//!     explaining why.
+//!   * `empty`: it's treated as an empty file, without creating it.
+//!   * `error`: [`Error::NotFound`](enum.Error.html#variant.NotFound) is
+//!     returned.
+//! * `file_mode = ...`: the Unix permissions of the config file, like
+//!   `0o600` for files that hold credentials. They're set every time it's
+//!   written or created, including its templates. Otherwise, existing files
+//!   keep their permissions. Config files are always written atomically, and
+//!   their missing parent directories are created.
+//! * `version = ...`: the version of the config file's layout, starting at
+//!   1, which is written into its `[Metadata]` section. Older files are
+//!   migrated when they're loaded. See the [`migrate`](migrate/index.html)
+//!   module for more details.
+//! * `migrate = "path::to::fn"`: the function that migrates the raw
+//!   document of an older config file to the next version.
+//! * `rewrite_migrated`: the migrated config files are written back.
+//! * `deny_unknown`: the keys and sections in the config file that don't
+//!   belong to any option are rejected with
+//!   [`Error::Unknown`](enum.Error.html#variant.Unknown), suggesting the
+//!   most similar known name in case it's a typo, like
+//!   ``did you mean `timeout`?``.
+//! * `warn_unknown`: same as `deny_unknown`, but the unknown keys and
+//!   sections are only reported with a
+//!   [`Diagnostic`](diagnostics/enum.Diagnostic.html).
+//!
+//! ```rust
+//! use structconf::StructConf;
+//!
+//! #[derive(StructConf)]
+//! #[conf(env_prefix = "MYAPP_")]
+//! struct Server {
+//!     // Read from `MYAPP_TIMEOUT`
+//!     timeout: i32,
+//!     // Read from `MYAPP_HOST` instead of `MYAPP_ADDRESS`
+//!     #[conf(env = "MYAPP_HOST")]
+//!     address: String,
+//!     // Not read from the environment
+//!     #[conf(no_env)]
+//!     verbose: bool,
+//! }
+//! ```
 
 /// Re-exporting the `clap` module used in the macro.
 pub use clap;
+/// Re-exporting the shells available for the completions.
+#[cfg(feature = "completions")]
+pub use clap_complete::Shell;
+#[doc(hidden)]
+pub use context::Context;
+pub use diagnostics::Diagnostic;
+pub use format::FileFormat;
 /// Re-exporting the `ini` module used in the macro.
 pub use ini;
+pub use sources::{Source, Sources};
 pub use structconf_derive::StructConf;
+pub use write::{WriteMode, WriteOptions};
+
+mod context;
+pub mod diagnostics;
+pub mod format;
+pub mod meta;
+pub mod migrate;
+#[doc(hidden)]
+pub mod secret;
+mod sources;
+#[doc(hidden)]
+pub mod strict;
+#[doc(hidden)]
+pub mod validation;
+pub mod write;
 
 use std::ffi::OsString;
+use std::fmt;
 use std::io;
 
 /// Small wrapper for the possible errors that may occur when parsing a
@@ -102,8 +488,267 @@ pub enum Error {
     IO(#[from] io::Error),
     #[error("{0}")]
     Ini(ini::ParseError),
-    #[error("Error when parsing the config file: {0}")]
-    Parse(String),
+    #[error("{0}")]
+    Parse(Box<ParseError>),
+    #[error("Invalid config file format: {0}")]
+    Format(String),
+    #[error("Config file not found at {0}")]
+    NotFound(String),
+    #[error("A subcommand is required")]
+    MissingSubcommand,
+    #[error("The {name} is used by both `{first}` and `{second}`")]
+    Conflict {
+        name: String,
+        first: String,
+        second: String,
+    },
+    #[error(
+        "`{field}` is required, but it wasn't set. It can be set with {}",
+        either(.where_to_set)
+    )]
+    Missing {
+        field: String,
+        where_to_set: Vec<String>,
+    },
+    #[error("Couldn't read the secret for `{field}` from {path}: {source}")]
+    SecretFile {
+        field: String,
+        path: String,
+        source: io::Error,
+    },
+    #[error("{0}")]
+    Unknown(UnknownKey),
+    #[error("Couldn't migrate the config file at {path} from version {from}: {reason}")]
+    Migration {
+        path: String,
+        from: u32,
+        reason: String,
+    },
+    #[error("Invalid value `{value}` for `{field}` from the {origin}: {reason}")]
+    Validation {
+        field: String,
+        origin: Box<Source>,
+        value: String,
+        reason: String,
+    },
+    #[error("{}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"))]
+    Multiple(Vec<Error>),
+}
+
+impl Error {
+    /// Combines the errors obtained with
+    /// [`parse_file_all_errors`](trait.StructConf.html#tymethod.parse_file_all_errors)
+    /// into a single one, or returns `Ok` if there are none.
+    pub fn from_all(errors: Vec<Error>) -> Result<(), Error> {
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Multiple(errors))
+        }
+    }
+
+    /// Not part of the public API; used by the derived code to add the line
+    /// number to the parse errors in the config file, if it can be found.
+    #[doc(hidden)]
+    pub fn locate(self) -> Error {
+        match self {
+            Error::Parse(mut err) => {
+                if let Source::File {
+                    path,
+                    section,
+                    key: Some(key),
+                } = &err.source
+                {
+                    if let Ok(contents) = std::fs::read_to_string(path) {
+                        err.line = format::find_line(&contents, section, key);
+                    }
+                }
+                Error::Parse(err)
+            }
+            err => err,
+        }
+    }
+}
+
+/// Lists the alternatives in `items`, like "a, b or c".
+fn either(items: &[String]) -> String {
+    match items {
+        [] => String::new(),
+        [item] => item.clone(),
+        [rest @ .., last] => format!("{} or {}", rest.join(", "), last),
+    }
+}
+
+/// Not part of the public API; used by the derived code to either return
+/// the first error found when initializing a struct, or to collect all of
+/// them while the failing fields take their default value.
+#[doc(hidden)]
+pub struct Errors {
+    collect: bool,
+    errors: Vec<Error>,
+    incomplete: bool,
+}
+
+impl Errors {
+    pub fn new(collect: bool) -> Errors {
+        Errors {
+            collect,
+            errors: Vec::new(),
+            incomplete: false,
+        }
+    }
+
+    pub fn push(&mut self, err: Error) {
+        self.errors.push(err);
+    }
+
+    pub fn check<T>(
+        &mut self,
+        result: Result<T, Error>,
+        default: impl FnOnce() -> T,
+    ) -> Result<T, Error> {
+        match result {
+            Err(err) if self.collect => {
+                self.errors.push(err);
+                Ok(default())
+            }
+            result => result,
+        }
+    }
+
+    /// Same as `check`, but for required fields, which have no default
+    /// value. Their errors are collected as well, but the struct can't be
+    /// initialized then.
+    pub fn check_required<T>(&mut self, result: Result<T, Error>) -> Result<Option<T>, Error> {
+        match result {
+            Err(err) if self.collect => {
+                self.errors.push(err);
+                self.incomplete = true;
+                Ok(None)
+            }
+            result => result.map(Some),
+        }
+    }
+
+    /// Returned by the structs that couldn't be initialized because of a
+    /// required field. It's discarded by `finish`, since the field's error
+    /// was already collected.
+    pub fn incomplete(&self) -> Error {
+        Error::Multiple(Vec::new())
+    }
+
+    /// The struct initialized with the collected errors, if it could be.
+    pub fn finish<T>(mut self, result: Result<T, Error>) -> (Option<T>, Vec<Error>) {
+        let conf = match result {
+            Ok(conf) => Some(conf),
+            Err(_) if self.incomplete => None,
+            Err(err) => {
+                self.errors.push(err);
+                None
+            }
+        };
+        (conf, self.into_vec())
+    }
+
+    pub fn into_vec(self) -> Vec<Error> {
+        self.errors.into_iter().map(Error::locate).collect()
+    }
+}
+
+/// The details of a value that couldn't be parsed, which are shown in the
+/// style of a compiler diagnostic:
+///
+/// ```text
+/// error: invalid value `abc` for `timeout`
+///   --> config.ini:3, key `timeout` in section `[Defaults]`
+///    = expected `i32`: invalid digit found in string
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// The field's identifier, prefixed by its parent's in nested structs,
+    /// like `database.port`.
+    pub field: String,
+    /// Where the value was obtained from.
+    pub source: Source,
+    /// The value as it was written.
+    pub value: String,
+    /// The name of the type the value should have.
+    pub expected: String,
+    /// The line in the config file, if available.
+    pub line: Option<usize>,
+    /// Why the value couldn't be parsed.
+    pub reason: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "error: invalid value `{}` for `{}`",
+            self.value, self.field
+        )?;
+        match &self.source {
+            Source::File { path, section, key } => {
+                write!(f, "  --> {}", path)?;
+                if let Some(line) = self.line {
+                    write!(f, ":{}", line)?;
+                }
+                match key {
+                    Some(key) => writeln!(f, ", key `{}` in section `[{}]`", key, section)?,
+                    None => writeln!(f, ", section `[{}]`", section)?,
+                }
+            }
+            source => writeln!(f, "  --> {}", source)?,
+        }
+        write!(f, "   = expected `{}`: {}", self.expected, self.reason)
+    }
+}
+
+/// A key or a section in the config file that doesn't belong to any
+/// option, found with the `deny_unknown` or `warn_unknown` attributes:
+///
+/// ```text
+/// Unknown key `timout` in section `[Defaults]` of config.ini, did you mean `timeout`?
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownKey {
+    /// The path of the config file.
+    pub path: String,
+    /// The section in the config file, or `None` for the keys outside of
+    /// any section.
+    pub section: Option<String>,
+    /// The unknown key, or `None` if the whole section is unknown.
+    pub key: Option<String>,
+    /// The most similar known key or section, if any is close enough.
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.section, &self.key) {
+            (Some(section), Some(key)) => write!(
+                f,
+                "Unknown key `{}` in section `[{}]` of {}",
+                key, section, self.path
+            )?,
+            (None, Some(key)) => write!(
+                f,
+                "Unknown key `{}` outside of any section in {}",
+                key, self.path
+            )?,
+            (section, None) => write!(
+                f,
+                "Unknown section `[{}]` in {}",
+                section.as_deref().unwrap_or_default(),
+                self.path
+            )?,
+        }
+        match (&self.suggestion, &self.key) {
+            (Some(suggestion), Some(_)) => write!(f, ", did you mean `{}`?", suggestion),
+            (Some(suggestion), None) => write!(f, ", did you mean `[{}]`?", suggestion),
+            (None, _) => Ok(()),
+        }
+    }
 }
 
 impl From<ini::Error> for Error {
@@ -118,19 +763,25 @@ impl From<ini::Error> for Error {
 /// This trait implements the methods available after using
 /// `#[derive(StructConf)]`.
 ///
-/// The priority followed for the configuration is "arguments > config file >
-/// default values".
+/// The priority followed for the configuration is "arguments > environment >
+/// config file > default values".
 pub trait StructConf {
-    /// Instantiate the structure from both the argument parser and the
-    /// config file, falling back to the default values. Equivalent to
-    /// calling `parse_args` and then `parse_file`.
+    /// Instantiate the structure from the argument parser, the environment
+    /// and the config file, falling back to the default values. Equivalent
+    /// to calling `parse_args` and then `parse_file`.
     ///
-    /// The `path` argument is where the config file will be. If it doesn't
-    /// exist, it will be created, and a message to stderr will be printed.
+    /// The `path` argument is where the config file will be. See
+    /// `parse_file` for what happens if it doesn't exist.
     fn parse(app: clap::App, path: &str) -> Result<Self, Error>
     where
         Self: Sized;
 
+    /// Same as `parse`, but it also returns the source each field's value
+    /// was obtained from.
+    fn parse_with_sources(app: clap::App, path: &str) -> Result<(Self, Sources), Error>
+    where
+        Self: Sized;
+
     /// Parses only the arguments with [clap](
     /// https://docs.rs/clap/2.33.1/clap/). This is useful for a
     /// `--config-file` argument to allow the user to choose the config
@@ -146,18 +797,197 @@ pub trait StructConf {
         I: IntoIterator<Item = T>,
         T: Into<OsString> + Clone;
 
-    /// The config file is read after parsing the arguments, and the struct
-    /// is initialized with the default values taken into account.
+    /// The environment and the config file are read after parsing the
+    /// arguments, and the struct is initialized with the default values
+    /// taken into account.
     ///
     /// The `path` argument is where the config file will be. If it doesn't
-    /// exist, it will be created, and a message to stderr will be printed.
+    /// exist, it will be created by default, and a
+    /// [`Diagnostic`](diagnostics/enum.Diagnostic.html) will be emitted. This
+    /// can be changed with the `missing_file` attribute.
     ///
     /// This also serves as a function to refresh the config file values.
     fn parse_file(args: &clap::ArgMatches, path: &str) -> Result<Self, Error>
     where
         Self: Sized;
 
+    /// Same as `parse_file`, but instead of failing on the first error, it
+    /// tries to initialize every field and returns all the errors found.
+    /// The fields that couldn't be parsed take their default value, so that
+    /// the rest can still be used. The errors can be combined into a single
+    /// one with [`Error::from_all`](enum.Error.html#method.from_all).
+    ///
+    /// The struct is only `None` when a `required` field isn't set, since
+    /// it has no default value to take. Its error is returned along with
+    /// the rest.
+    fn parse_file_all_errors(args: &clap::ArgMatches, path: &str) -> (Option<Self>, Vec<Error>)
+    where
+        Self: Sized;
+
+    /// Same as `parse_file`, but it also returns the source each field's
+    /// value was obtained from.
+    fn parse_file_with_sources(
+        args: &clap::ArgMatches,
+        path: &str,
+    ) -> Result<(Self, Sources), Error>
+    where
+        Self: Sized;
+
+    /// Instantiate the structure from the argument parser, the environment
+    /// and multiple layers of config files, falling back to the default
+    /// values. Equivalent to calling `parse_args` and then
+    /// `parse_file_layers`.
+    fn parse_layers(app: clap::App, paths: &[&str]) -> Result<Self, Error>
+    where
+        Self: Sized;
+
+    /// Same as `parse_file`, but with multiple layers of config files, like
+    /// `&["/etc/myapp.ini", "/home/user/.config/myapp.ini"]`. The keys in
+    /// the later files replace the ones in the earlier files, and the
+    /// arguments and the environment still take priority over all of them.
+    ///
+    /// Unlike `parse_file`, missing files are skipped instead of created.
+    fn parse_file_layers(args: &clap::ArgMatches, paths: &[&str]) -> Result<Self, Error>
+    where
+        Self: Sized;
+
+    /// Same as `parse_file_layers`, but it also returns the source each
+    /// field's value was obtained from, including the layer.
+    fn parse_file_layers_with_sources(
+        args: &clap::ArgMatches,
+        paths: &[&str],
+    ) -> Result<(Self, Sources), Error>
+    where
+        Self: Sized;
+
+    /// Information about every option in the struct, including the ones in
+    /// nested structs.
+    fn options() -> Vec<meta::OptionInfo>
+    where
+        Self: Sized;
+
+    /// Writes a template for the config file into `path`, with every option
+    /// commented out with its default value and its help message. See
+    /// [`meta::template`](meta/fn.template.html) for more details.
+    fn write_template(path: &str) -> Result<(), Error>
+    where
+        Self: Sized;
+
+    /// Generates a JSON Schema describing the config file, which can be used
+    /// to lint it in editors. See [`meta::json_schema`](meta/fn.json_schema.html)
+    /// for more details.
+    fn json_schema() -> String
+    where
+        Self: Sized,
+    {
+        meta::json_schema(&Self::options())
+    }
+
     /// Writes the structure's values into a config file, except for those
-    /// that are wrapped by `Option` and whose value is `None`.
+    /// that are wrapped by `Option` and whose value is `None`. With layers,
+    /// `path` can be any of them.
     fn write_file(&self, path: &str) -> Result<(), Error>;
+
+    /// Same as `write_file`, but with custom [`WriteOptions`], like a
+    /// [`WriteMode`] to only write the values that differ from their
+    /// defaults. For example, to only update the struct's keys in an
+    /// existing config file, keeping its comments and any other keys:
+    ///
+    /// ```rust
+    /// use structconf::{StructConf, WriteOptions};
+    ///
+    /// #[derive(StructConf)]
+    /// struct Config {
+    ///     timeout: i32,
+    /// }
+    ///
+    /// # fn main() -> Result<(), structconf::Error> {
+    /// # let path = std::env::temp_dir().join("structconf-write-file-with.ini");
+    /// # let path = path.to_str().unwrap();
+    /// # std::fs::write(path, "; Seconds\n[Defaults]\ntimeout = 1\n")?;
+    /// let config = Config { timeout: 30 };
+    /// config.write_file_with(path, &WriteOptions::new().preserve_layout(true))?;
+    /// # assert_eq!(
+    /// #     std::fs::read_to_string(path)?,
+    /// #     "; Seconds\n[Defaults]\ntimeout = 30\n"
+    /// # );
+    /// # std::fs::remove_file(path)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn write_file_with(&self, path: &str, options: &WriteOptions) -> Result<(), Error>;
+
+    /// Writes the shell completion script for the struct's arguments into
+    /// `buf`, for a program named `bin_name`. It requires the `completions`
+    /// cargo feature.
+    ///
+    /// ```rust
+    /// use structconf::{Shell, StructConf};
+    ///
+    /// #[derive(StructConf)]
+    /// struct Config {
+    ///     #[conf(possible_values = "&[\"fast\", \"slow\"]")]
+    ///     speed: String,
+    /// }
+    ///
+    /// let mut script = Vec::new();
+    /// Config::completions(Shell::Bash, "myapp", &mut script);
+    /// ```
+    #[cfg(feature = "completions")]
+    fn completions(shell: Shell, bin_name: &str, buf: &mut impl std::io::Write)
+    where
+        Self: Sized,
+    {
+        let ctx = Context::root();
+        let app = clap::App::new(bin_name.to_string()).args(Self::__args(&ctx));
+        let mut app = Self::__subcommands(app, &ctx);
+        clap_complete::generate(shell, &mut app, bin_name, buf);
+    }
+
+    /// Not part of the public API; used by the derived code to obtain the
+    /// arguments of the struct in a context, so that it can be nested.
+    #[doc(hidden)]
+    fn __args<'a>(ctx: &Context) -> Vec<clap::Arg<'a>>;
+
+    /// Not part of the public API; used by the derived code to add the
+    /// subcommands of the struct to `app` in a context, so that it can be
+    /// nested.
+    #[doc(hidden)]
+    fn __subcommands<'a>(app: clap::App<'a>, ctx: &Context) -> clap::App<'a>
+    where
+        Self: Sized;
+
+    /// Not part of the public API; used by the derived code to initialize
+    /// the struct in a context, so that it can be nested.
+    #[doc(hidden)]
+    fn __init(
+        args: &clap::ArgMatches,
+        file: &ini::Ini,
+        ctx: &Context,
+        sources: &mut Sources,
+        errors: &mut Errors,
+    ) -> Result<Self, Error>
+    where
+        Self: Sized;
+
+    /// Not part of the public API; used by the derived code to obtain the
+    /// information about the options in a context, so that it can be
+    /// nested.
+    #[doc(hidden)]
+    fn __options(ctx: &Context) -> Vec<meta::OptionInfo>
+    where
+        Self: Sized;
+
+    /// Not part of the public API; used by the derived code to obtain the
+    /// information about the options in use by the struct's values, which
+    /// excludes the subcommands that weren't used.
+    #[doc(hidden)]
+    fn __owned_options(&self, ctx: &Context) -> Vec<meta::OptionInfo>
+    where
+        Self: Sized;
+
+    /// Not part of the public API; used by the derived code to write the
+    /// struct's values in a context, so that it can be nested.
+    #[doc(hidden)]
+    fn __write(&self, conf: &mut ini::Ini, ctx: &Context);
 }
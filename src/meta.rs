@@ -0,0 +1,779 @@
+//! Information about the options of a StructConf-derived struct, obtained
+//! with [`StructConf::options`](../trait.StructConf.html#tymethod.options).
+//! It's useful to generate documentation or templates for the config file.
+//! Both a man page and a Markdown reference can be generated from it as
+//! well, covering every option in the arguments, the environment and the
+//! config file:
+//!
+//! ```rust
+//! use structconf::{meta, StructConf};
+//!
+//! #[derive(StructConf)]
+//! struct Config {
+//!     #[conf(help = "Seconds to wait", default = "30")]
+//!     timeout: u32,
+//! }
+//!
+//! let page = meta::man_page("myapp", "does things", &Config::options());
+//! assert!(page.contains("\\fB\\-\\-timeout\\fR"));
+//! let reference = meta::markdown("myapp", "does things", &Config::options());
+//! assert!(reference.contains("### `timeout`"));
+//! ```
+
+use crate::{format, write, Error};
+
+use std::ops::Bound;
+use std::path::Path;
+
+/// The kind of value an option holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueKind {
+    /// A boolean flag in the argument parser, which doesn't take a value.
+    Flag,
+    /// A single value.
+    Single,
+    /// A value wrapped by `Option`.
+    Optional,
+    /// A list of values, with `Vec`.
+    List,
+    /// A map of values, with `HashMap` or `BTreeMap`.
+    Map,
+}
+
+/// Information about an option, which corresponds to a field in the struct.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OptionInfo {
+    /// The field's identifier, prefixed by its parent's in nested structs,
+    /// like `database.port`.
+    pub field: String,
+    /// The name of the type of the values, like `i32`.
+    pub ty: String,
+    /// The kind of value it holds.
+    pub kind: ValueKind,
+    /// The help message.
+    pub help: Option<String>,
+    /// The long argument, without the leading dashes.
+    pub long: Option<String>,
+    /// The short argument.
+    pub short: Option<char>,
+    /// The environment variable.
+    pub env: Option<String>,
+    /// The section in the config file, if it's available in it.
+    pub section: Option<String>,
+    /// The key in the config file. Maps take their whole section, so they
+    /// don't have one.
+    pub key: Option<String>,
+    /// The default values as they would be written in the config file.
+    /// There may be none, like for `Option<T>`, or multiple, like for lists.
+    /// The entries of maps are written as `key = value`. They're only
+    /// available for options in the config file.
+    pub default: Vec<String>,
+    /// The delimiter of lists, if they have one.
+    pub delimiter: Option<char>,
+    /// The values the argument may take, from `possible_values`. Any value
+    /// is accepted if it's empty.
+    pub possible_values: Vec<String>,
+    /// The constraints from the validation attributes.
+    pub validation: Validation,
+    /// Whether it's a secret, which isn't written into the config file
+    /// unless allowed with [`WriteOptions::secrets`](../write/struct.WriteOptions.html#method.secrets).
+    /// Its value may also be read from a file, with the argument, variable
+    /// or key suffixed by `-file`, `_FILE` or `_file`, respectively.
+    pub secret: bool,
+    /// The former keys of the option in the config file, from `alias`, which
+    /// are still accepted. They're not documented.
+    pub aliases: Vec<String>,
+    /// The subcommands the option belongs to, from the outermost one. It's
+    /// empty for the options of the struct being parsed.
+    pub subcommands: Vec<String>,
+}
+
+/// The constraints on the values of an option, from its validation
+/// attributes. Custom functions in `validate` can't be described.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Validation {
+    /// The bounds in `range`, as they would be written in the config file.
+    /// It's only available for options in the config file.
+    pub range: Option<(Bound<String>, Bound<String>)>,
+    /// The regular expression in `regex`, which must match the entire
+    /// value.
+    pub regex: Option<String>,
+    /// Whether the value can't be empty or only whitespace.
+    pub non_empty: bool,
+}
+
+/// Generates a template for the config file in the INI format. Every option
+/// in the config file is included, grouped by section, with its help
+/// message as a comment and its default value commented out, so that the
+/// template has no effect until it's edited.
+pub fn template(options: &[OptionInfo]) -> String {
+    let mut out = String::new();
+    for (section, opts) in sections(options) {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+
+        // The section of a map is documented by its help message. It's
+        // commented out as well, since even an empty section would replace
+        // the default value.
+        if let [opt @ OptionInfo { key: None, .. }] = opts.as_slice() {
+            push_comment(&mut out, opt.help.as_deref());
+            out.push_str(&format!("; [{}]\n", section));
+            for entry in &opt.default {
+                out.push_str(&format!("; {}\n", entry));
+            }
+            continue;
+        }
+
+        out.push_str(&format!("[{}]\n", section));
+        for (i, opt) in opts.iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+            push_comment(&mut out, opt.help.as_deref());
+            let key = opt.key.as_deref().unwrap_or_default();
+            // The defaults of secrets aren't shown.
+            if opt.default.is_empty() || opt.secret {
+                out.push_str(&format!("; {} =\n", key));
+                continue;
+            }
+            for val in &opt.default {
+                out.push_str(&format!("; {} = {}\n", key, val));
+            }
+        }
+    }
+
+    out
+}
+
+/// Writes the template generated by [`template`] into `path`, with the
+/// permissions in `mode` if present. Templates are only available for INI
+/// config files, since the rest of formats either don't support comments or
+/// have a different syntax for values.
+pub fn write_template(
+    options: &[OptionInfo],
+    path: &str,
+    name: Option<&str>,
+    mode: Option<u32>,
+) -> Result<(), Error> {
+    save_template(&template(options), path, name, mode)
+}
+
+/// Writes the contents of a template into `path`, checking that it's an INI
+/// config file.
+pub(crate) fn save_template(
+    template: &str,
+    path: &str,
+    name: Option<&str>,
+    mode: Option<u32>,
+) -> Result<(), Error> {
+    if !format::is_ini(Path::new(path), name) {
+        return Err(Error::Format(
+            "templates are only available for INI config files".to_string(),
+        ));
+    }
+
+    write::atomic_write(Path::new(path), template.as_bytes(), mode)?;
+    Ok(())
+}
+
+/// Not part of the public API; used by the derived code to check that the
+/// arguments of flattened structs and subcommands don't collide with each
+/// other, since this can only be detected once they're combined. clap would
+/// otherwise panic in debug builds, and silently use one of them in release
+/// builds.
+#[doc(hidden)]
+pub fn check_args(app: &clap::App) -> Result<(), Error> {
+    let mut seen = std::collections::HashMap::new();
+    for arg in app.get_arguments() {
+        let mut names = Vec::new();
+        if let Some(long) = arg.get_long() {
+            names.push(format!("argument `--{}`", long));
+        }
+        if let Some(short) = arg.get_short() {
+            names.push(format!("argument `-{}`", short));
+        }
+        for name in names {
+            if let Some(first) = seen.insert(name.clone(), arg.get_id()) {
+                return Err(Error::Conflict {
+                    name,
+                    first: first.to_string(),
+                    second: arg.get_id().to_string(),
+                });
+            }
+        }
+    }
+
+    // Each subcommand has its own arguments.
+    app.get_subcommands().try_for_each(check_args)
+}
+
+/// Not part of the public API; used by the derived code to check that the
+/// keys in the config file of flattened or nested structs don't collide
+/// with each other, like `check_args`. Otherwise, one of them would
+/// silently take the value of the other.
+#[doc(hidden)]
+pub fn check_keys(options: &[OptionInfo]) -> Result<(), Error> {
+    let mut seen = std::collections::HashMap::new();
+    for opt in options {
+        let section = match &opt.section {
+            Some(section) => section,
+            None => continue,
+        };
+        let mut names = match &opt.key {
+            Some(key) => vec![format!("key `{}` in section `[{}]`", key, section)],
+            None => vec![format!("section `[{}]`", section)],
+        };
+        for alias in &opt.aliases {
+            names.push(format!("key `{}` in section `[{}]`", alias, section));
+        }
+        for name in names {
+            if let Some(first) = seen.insert(name.clone(), &opt.field) {
+                return Err(Error::Conflict {
+                    name,
+                    first: first.to_string(),
+                    second: opt.field.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Not part of the public API; used by the derived code to check that the
+/// environment variables of flattened or nested structs don't collide with
+/// each other, like `check_keys`. The ones of different subcommands may be
+/// shared, since only one of them is used at a time.
+#[doc(hidden)]
+pub fn check_envs(options: &[OptionInfo]) -> Result<(), Error> {
+    let mut seen: Vec<(String, &OptionInfo)> = Vec::new();
+    for opt in options {
+        let env = match &opt.env {
+            Some(env) => env,
+            None => continue,
+        };
+        let mut names = vec![format!("environment variable `{}`", env)];
+        if opt.secret {
+            names.push(format!("environment variable `{}_FILE`", env));
+        }
+        for name in names {
+            // Both are read only if one of them is in the subcommands of
+            // the other.
+            let first = seen.iter().find(|(seen_name, first)| {
+                let len = first.subcommands.len().min(opt.subcommands.len());
+                *seen_name == name && first.subcommands[..len] == opt.subcommands[..len]
+            });
+            if let Some((_, first)) = first {
+                return Err(Error::Conflict {
+                    name,
+                    first: first.field.clone(),
+                    second: opt.field.clone(),
+                });
+            }
+            seen.push((name, opt));
+        }
+    }
+
+    Ok(())
+}
+
+/// Generates a man page in the roff format for the program `name`, with a
+/// short description in `about`. Every option is listed with its arguments,
+/// environment variable, key in the config file, type, default value and
+/// help message. The layout of the config file is described in the FILES
+/// section with its [`template`].
+pub fn man_page(name: &str, about: &str, options: &[OptionInfo]) -> String {
+    let mut out = format!(".TH {} 1\n", roff(&name.to_uppercase()));
+    out.push_str(&format!(".SH NAME\n{} \\- {}\n", roff(name), roff(about)));
+    out.push_str(&format!(
+        ".SH SYNOPSIS\n\\fB{}\\fR [\\fIOPTIONS\\fR]\n",
+        roff(name)
+    ));
+
+    out.push_str(".SH OPTIONS\n");
+    for opt in options.iter().filter(|opt| is_configurable(opt)) {
+        out.push_str(".TP\n");
+        let args = args(opt);
+        if args.is_empty() {
+            out.push_str(&format!("\\fB{}\\fR\n", roff(&opt.field)));
+        } else {
+            let args = args
+                .iter()
+                .map(|arg| format!("\\fB{}\\fR", roff(arg)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            match placeholder(opt) {
+                Some(val) => out.push_str(&format!("{} \\fI{}\\fR\n", args, val)),
+                None => out.push_str(&format!("{}\n", args)),
+            }
+        }
+
+        let mut lines = Vec::new();
+        if let Some(help) = &opt.help {
+            lines.push(roff(help));
+        }
+        for (title, val) in details(opt, |text| format!("\\fB{}\\fR", roff(text))) {
+            lines.push(format!("{}: {}", title, val));
+        }
+        out.push_str(&lines.join("\n.br\n"));
+        out.push('\n');
+    }
+
+    let template = template(options);
+    if !template.is_empty() {
+        out.push_str(".SH FILES\n");
+        out.push_str("The options in the config file, with their default values:\n");
+        out.push_str(".PP\n.RS\n.nf\n");
+        for line in template.lines() {
+            out.push_str(&roff(line));
+            out.push('\n');
+        }
+        out.push_str(".fi\n.RE\n");
+    }
+
+    out
+}
+
+/// Generates a reference in Markdown for the program `name`, with a short
+/// description in `about`. It contains the same information as the
+/// [`man_page`].
+pub fn markdown(name: &str, about: &str, options: &[OptionInfo]) -> String {
+    let mut out = format!("# {}\n\n{}\n\n## Options\n", name, about);
+    for opt in options.iter().filter(|opt| is_configurable(opt)) {
+        out.push_str(&format!("\n### `{}`\n\n", opt.field));
+        if let Some(help) = &opt.help {
+            out.push_str(&format!("{}\n\n", help));
+        }
+
+        out.push_str("| | |\n| --- | --- |\n");
+        let args = args(opt);
+        if !args.is_empty() {
+            let args = args
+                .iter()
+                .map(|arg| match placeholder(opt) {
+                    Some(val) => format!("`{} <{}>`", arg, val),
+                    None => format!("`{}`", arg),
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            out.push_str(&format!("| Arguments | {} |\n", args));
+        }
+        for (title, val) in details(opt, |text| format!("`{}`", text.replace('|', "\\|"))) {
+            out.push_str(&format!("| {} | {} |\n", title, val));
+        }
+    }
+
+    let template = template(options);
+    if !template.is_empty() {
+        out.push_str("\n## Config file\n\n");
+        out.push_str("The options in the config file, with their default values:\n\n");
+        out.push_str(&format!("```ini\n{}```\n", template));
+    }
+
+    out
+}
+
+/// Generates a [JSON Schema](https://json-schema.org) describing the config
+/// file, with an object for each section and a property for each key in
+/// it. The sections of nested structs, like `Database.Replica`, are nested
+/// objects. Each property has its type, default value, help message and
+/// validation constraints, when they're known. For types other than
+/// numbers, booleans, strings and paths, the type is only known if it has
+/// `possible_values`.
+///
+/// The values are described as they're written in the TOML, JSON and YAML
+/// formats, where lists are arrays and numbers aren't quoted. In INI config
+/// files, every value is a string instead.
+pub fn json_schema(options: &[OptionInfo]) -> String {
+    let mut root = SectionTree::default();
+    for (section, opts) in self::sections(options) {
+        // Maps take the whole section.
+        if let [opt @ OptionInfo { key: None, .. }] = opts.as_slice() {
+            let mut schema = describe(opt);
+            schema.push(("type", Json::Str("object".to_string())));
+            schema.push(("additionalProperties", Json::Object(value_schema(opt))));
+            if !opt.default.is_empty() {
+                let entries = opt
+                    .default
+                    .iter()
+                    .filter_map(|entry| entry.split_once(" = "))
+                    .map(|(key, val)| (key.to_string(), json_value(opt, val)))
+                    .collect();
+                schema.push(("default", Json::Map(entries)));
+            }
+            root.insert(section, schema);
+            continue;
+        }
+
+        let props = opts
+            .iter()
+            .map(|opt| {
+                let key = opt.key.clone().unwrap_or_default();
+                (key, Json::Object(key_schema(opt)))
+            })
+            .collect();
+        let schema = vec![
+            ("type", Json::Str("object".to_string())),
+            ("properties", Json::Map(props)),
+        ];
+        root.insert(section, schema);
+    }
+
+    let schema = Json::Object(vec![
+        (
+            "$schema",
+            Json::Str("https://json-schema.org/draft/2020-12/schema".to_string()),
+        ),
+        ("type", Json::Str("object".to_string())),
+        ("properties", Json::Map(root.properties())),
+    ]);
+    let mut out = String::new();
+    schema.write(&mut out, 0);
+    out.push('\n');
+    out
+}
+
+/// The schemas of the sections, where the dots in their names are nested
+/// objects, like the tables in the TOML, JSON and YAML formats.
+#[derive(Default)]
+struct SectionTree {
+    schema: Option<Vec<(&'static str, Json)>>,
+    children: Vec<(String, SectionTree)>,
+}
+
+impl SectionTree {
+    /// Inserts the schema of a section, creating its missing parents.
+    fn insert(&mut self, section: &str, schema: Vec<(&'static str, Json)>) {
+        let mut node = self;
+        for name in section.split('.') {
+            let pos = match node.children.iter().position(|(child, _)| child == name) {
+                Some(pos) => pos,
+                None => {
+                    node.children
+                        .push((name.to_string(), SectionTree::default()));
+                    node.children.len() - 1
+                }
+            };
+            node = &mut node.children[pos].1;
+        }
+        node.schema = Some(schema);
+    }
+
+    /// The schemas of the nested sections, as properties of their parent.
+    fn properties(self) -> Vec<(String, Json)> {
+        self.children
+            .into_iter()
+            .map(|(name, child)| (name, child.into_schema()))
+            .collect()
+    }
+
+    /// The schema of a section, with its nested sections added to its
+    /// properties. The parents without options of their own are plain
+    /// objects.
+    fn into_schema(mut self) -> Json {
+        let mut schema = self.schema.take().unwrap_or_else(|| {
+            vec![
+                ("type", Json::Str("object".to_string())),
+                ("properties", Json::Map(Vec::new())),
+            ]
+        });
+        let children = self.properties();
+        if !children.is_empty() {
+            match schema.iter_mut().find(|(name, _)| *name == "properties") {
+                Some((_, Json::Map(props))) => props.extend(children),
+                _ => schema.push(("properties", Json::Map(children))),
+            }
+        }
+        Json::Object(schema)
+    }
+}
+
+/// A JSON value with its keys in order, only for what's needed in the
+/// schema. Raw values are numbers and booleans, written as they are.
+enum Json {
+    Raw(String),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+    Map(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Writes the value with two spaces of indentation per level.
+    fn write(&self, out: &mut String, indent: usize) {
+        fn write_entries<'a>(
+            out: &mut String,
+            indent: usize,
+            entries: impl ExactSizeIterator<Item = (&'a str, &'a Json)>,
+        ) {
+            if entries.len() == 0 {
+                out.push_str("{}");
+                return;
+            }
+            out.push_str("{\n");
+            let len = entries.len();
+            for (i, (key, val)) in entries.enumerate() {
+                out.push_str(&"  ".repeat(indent + 1));
+                out.push_str(&format!("{}: ", quote(key)));
+                val.write(out, indent + 1);
+                out.push_str(if i + 1 < len { ",\n" } else { "\n" });
+            }
+            out.push_str(&"  ".repeat(indent));
+            out.push('}');
+        }
+
+        match self {
+            Json::Raw(val) => out.push_str(val),
+            Json::Str(val) => out.push_str(&quote(val)),
+            Json::Array(vals) => {
+                let vals = vals
+                    .iter()
+                    .map(|val| {
+                        let mut out = String::new();
+                        val.write(&mut out, indent);
+                        out
+                    })
+                    .collect::<Vec<_>>();
+                out.push_str(&format!("[{}]", vals.join(", ")));
+            }
+            Json::Object(entries) => {
+                write_entries(out, indent, entries.iter().map(|(key, val)| (*key, val)))
+            }
+            Json::Map(entries) => write_entries(
+                out,
+                indent,
+                entries.iter().map(|(key, val)| (key.as_str(), val)),
+            ),
+        }
+    }
+}
+
+/// Quotes and escapes a JSON string.
+fn quote(text: &str) -> String {
+    let mut out = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The JSON type of an option's values, if it's known.
+fn json_type(opt: &OptionInfo) -> Option<&'static str> {
+    const INTEGERS: &[&str] = &[
+        "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64", "u128", "usize",
+    ];
+    match opt.ty.as_str() {
+        ty if INTEGERS.contains(&ty) => Some("integer"),
+        "f32" | "f64" => Some("number"),
+        "bool" => Some("boolean"),
+        "String" | "char" | "PathBuf" => Some("string"),
+        _ if !opt.possible_values.is_empty() => Some("string"),
+        _ => None,
+    }
+}
+
+/// A value as written in the config file, converted to its JSON type.
+fn json_value(opt: &OptionInfo, val: &str) -> Json {
+    let valid = match json_type(opt) {
+        Some("integer") => val.parse::<i128>().is_ok() || val.parse::<u128>().is_ok(),
+        Some("number") => val.parse::<f64>().is_ok_and(f64::is_finite),
+        Some("boolean") => val == "true" || val == "false",
+        _ => false,
+    };
+    if valid {
+        Json::Raw(val.to_string())
+    } else {
+        Json::Str(val.to_string())
+    }
+}
+
+/// The help message of an option.
+fn describe(opt: &OptionInfo) -> Vec<(&'static str, Json)> {
+    match &opt.help {
+        Some(help) => vec![("description", Json::Str(help.clone()))],
+        None => Vec::new(),
+    }
+}
+
+/// The schema of a single value of an option, with its constraints.
+fn value_schema(opt: &OptionInfo) -> Vec<(&'static str, Json)> {
+    let mut schema = Vec::new();
+    let ty = json_type(opt);
+    if let Some(ty) = ty {
+        schema.push(("type", Json::Str(ty.to_string())));
+    }
+    if !opt.possible_values.is_empty() {
+        let vals = opt.possible_values.iter().cloned().map(Json::Str).collect();
+        schema.push(("enum", Json::Array(vals)));
+    }
+
+    let validation = &opt.validation;
+    if let (Some((start, end)), Some("integer" | "number")) = (&validation.range, ty) {
+        match start {
+            Bound::Included(val) => schema.push(("minimum", json_value(opt, val))),
+            Bound::Excluded(val) => schema.push(("exclusiveMinimum", json_value(opt, val))),
+            Bound::Unbounded => {}
+        }
+        match end {
+            Bound::Included(val) => schema.push(("maximum", json_value(opt, val))),
+            Bound::Excluded(val) => schema.push(("exclusiveMaximum", json_value(opt, val))),
+            Bound::Unbounded => {}
+        }
+    }
+    if let Some(regex) = &validation.regex {
+        schema.push(("pattern", Json::Str(format!("^(?:{})$", regex))));
+    } else if validation.non_empty {
+        schema.push(("pattern", Json::Str("\\S".to_string())));
+    }
+
+    schema
+}
+
+/// The schema of a key in the config file, with its default value.
+fn key_schema(opt: &OptionInfo) -> Vec<(&'static str, Json)> {
+    let mut schema = describe(opt);
+    if opt.kind != ValueKind::List {
+        schema.extend(value_schema(opt));
+        if let (false, Some(val)) = (opt.secret, opt.default.first()) {
+            schema.push(("default", json_value(opt, val)));
+        }
+        return schema;
+    }
+
+    schema.push(("type", Json::Str("array".to_string())));
+    schema.push(("items", Json::Object(value_schema(opt))));
+    let default = match (opt.delimiter, opt.default.first()) {
+        (Some(d), Some(val)) => format::split_list(val, d)
+            .iter()
+            .map(|val| json_value(opt, val))
+            .collect(),
+        _ => opt.default.iter().map(|val| json_value(opt, val)).collect(),
+    };
+    schema.push(("default", Json::Array(default)));
+
+    schema
+}
+
+/// The options grouped by their section in the config file, which are kept
+/// in the same order the options are declared in.
+fn sections(options: &[OptionInfo]) -> Vec<(&str, Vec<&OptionInfo>)> {
+    let mut sections: Vec<(&str, Vec<&OptionInfo>)> = Vec::new();
+    for opt in options {
+        let section = match &opt.section {
+            Some(section) => section.as_str(),
+            None => continue,
+        };
+        match sections.iter_mut().find(|(name, _)| *name == section) {
+            Some((_, opts)) => opts.push(opt),
+            None => sections.push((section, vec![opt])),
+        }
+    }
+
+    sections
+}
+
+/// Options that aren't available anywhere only take their default value,
+/// so they aren't documented.
+fn is_configurable(opt: &OptionInfo) -> bool {
+    opt.long.is_some() || opt.short.is_some() || opt.env.is_some() || opt.section.is_some()
+}
+
+/// The names of the arguments of an option, as written in the command line.
+fn args(opt: &OptionInfo) -> Vec<String> {
+    let short = opt.short.map(|short| format!("-{}", short));
+    let long = opt.long.as_ref().map(|long| format!("--{}", long));
+    short.into_iter().chain(long).collect()
+}
+
+/// The placeholder for the value of an argument, if it takes one.
+fn placeholder(opt: &OptionInfo) -> Option<&'static str> {
+    match opt.kind {
+        ValueKind::Flag => None,
+        ValueKind::Map => Some("KEY=VALUE"),
+        _ => Some("VALUE"),
+    }
+}
+
+/// The details of an option other than its arguments and its help message,
+/// as pairs of titles and values. `code` formats names and values.
+fn details(opt: &OptionInfo, code: impl Fn(&str) -> String) -> Vec<(&'static str, String)> {
+    let mut details = Vec::new();
+    if let Some(env) = &opt.env {
+        details.push(("Environment", code(env)));
+    }
+    match (&opt.section, &opt.key) {
+        (Some(section), Some(key)) => details.push((
+            "Config file",
+            format!("{} in {}", code(key), code(&format!("[{}]", section))),
+        )),
+        (Some(section), None) => {
+            details.push(("Config file", code(&format!("[{}]", section))));
+        }
+        _ => {}
+    }
+    let ty = code(&opt.ty);
+    let ty = match opt.kind {
+        ValueKind::Optional => format!("optional {}", ty),
+        ValueKind::List => format!("list of {}", ty),
+        ValueKind::Map => format!("map of {}", ty),
+        _ => ty,
+    };
+    details.push(("Type", ty));
+    if opt.secret {
+        let long = opt.long.as_ref().map(|long| format!("--{}-file", long));
+        let env = opt.env.as_ref().map(|env| format!("{}_FILE", env));
+        let key = opt.key.as_ref().map(|key| format!("{}_file", key));
+        let files = long.into_iter().chain(env).chain(key);
+        let files = files.map(|name| code(&name)).collect::<Vec<_>>();
+        if !files.is_empty() {
+            details.push(("Secret file", files.join(", ")));
+        }
+    } else if !opt.default.is_empty() {
+        let default = opt.default.iter().map(|val| code(val)).collect::<Vec<_>>();
+        details.push(("Default", default.join(", ")));
+    }
+
+    details
+}
+
+/// Escapes text for roff, so that it's shown as it is.
+fn roff(text: &str) -> String {
+    let text = text.replace('\\', "\\e").replace('-', "\\-");
+    if text.starts_with(['.', '\'']) {
+        format!("\\&{}", text)
+    } else {
+        text
+    }
+}
+
+/// Writes a help message as comments, wrapped to a readable width.
+fn push_comment(out: &mut String, help: Option<&str>) {
+    const WIDTH: usize = 78;
+
+    let help = match help {
+        Some(help) => help,
+        None => return,
+    };
+    let mut line = String::from(";");
+    for word in help.split_whitespace() {
+        if line.len() + 1 + word.len() > WIDTH && line.len() > 1 {
+            out.push_str(&line);
+            out.push('\n');
+            line = String::from(";");
+        }
+        line.push(' ');
+        line.push_str(word);
+    }
+    out.push_str(&line);
+    out.push('\n');
+}
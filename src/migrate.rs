@@ -0,0 +1,203 @@
+//! Versioning of the config file, so that older files can be migrated when
+//! their layout changes, like when options are renamed or moved to another
+//! section.
+//!
+//! A struct with the `version` attribute writes its version into the
+//! [`SECTION`] section of the config file, under the [`KEY`] key. The files
+//! without a version are considered to be from version 1, so that the ones
+//! written before the attribute was added can be migrated as well.
+//!
+//! When a file with an older version is loaded, the function in the
+//! `migrate` attribute is called once for every version in between, with
+//! the document and the version it's migrating from. It may then rename,
+//! convert or move the keys in the raw document, before the fields are
+//! extracted from it:
+//!
+//! ```rust
+//! use structconf::ini::Ini;
+//! use structconf::StructConf;
+//!
+//! #[derive(StructConf)]
+//! #[conf(version = 3, migrate = "migrate")]
+//! struct Config {
+//!     timeout_ms: u64,
+//! }
+//!
+//! fn migrate(doc: &mut Ini, from: u32) -> Result<(), String> {
+//!     match from {
+//!         // Version 2 renamed `timeout` to `timeout_secs`.
+//!         1 => {
+//!             if let Some(secs) = doc.delete_from(Some("Defaults"), "timeout") {
+//!                 doc.with_section(Some("Defaults")).set("timeout_secs", secs);
+//!             }
+//!         }
+//!         // Version 3 measured it in milliseconds.
+//!         2 => {
+//!             if let Some(secs) = doc.delete_from(Some("Defaults"), "timeout_secs") {
+//!                 let secs: u64 = secs.parse().map_err(|_| "invalid timeout")?;
+//!                 let millis = (secs * 1000).to_string();
+//!                 doc.with_section(Some("Defaults")).set("timeout_ms", millis);
+//!             }
+//!         }
+//!         _ => {}
+//!     }
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Every migration emits a [`Diagnostic::Migrated`](
+//! ../diagnostics/enum.Diagnostic.html#variant.Migrated). With the
+//! `rewrite_migrated` attribute, the migrated file is also written back, so
+//! that it's only migrated once. Note that it's rewritten as a whole, so
+//! the comments in INI files are lost. If it can't be written, a
+//! [`Diagnostic::RewriteFailed`](
+//! ../diagnostics/enum.Diagnostic.html#variant.RewriteFailed) is emitted
+//! instead, and the migrated values are used anyway.
+
+use crate::diagnostics::{self, Diagnostic};
+use crate::meta::{OptionInfo, Validation, ValueKind};
+use crate::{format, meta, Error};
+
+use ini::Ini;
+use std::path::Path;
+
+/// The section of the config file with its version.
+pub const SECTION: &str = "Metadata";
+
+/// The key in [`SECTION`] with the version of the config file.
+pub const KEY: &str = "version";
+
+/// A function that migrates the document of a config file from the given
+/// version to the next one.
+pub type Migration = fn(&mut Ini, u32) -> Result<(), String>;
+
+/// The version of the config file in `doc`, or 1 if it doesn't have one.
+pub fn version(doc: &Ini) -> Result<u32, Error> {
+    match doc.get_from(Some(SECTION), KEY) {
+        Some(version) => version.trim().parse().map_err(|_| {
+            Error::Format(format!(
+                "invalid version `{}` in section `[{}]`",
+                version, SECTION
+            ))
+        }),
+        None => Ok(1),
+    }
+}
+
+/// Not part of the public API; used by the derived code to migrate the
+/// document `doc` loaded from `path` to `version`, and to rewrite the file
+/// if `rewrite` is set. Empty documents, like the ones of missing files,
+/// are left as they are.
+#[doc(hidden)]
+pub fn run(
+    doc: &mut Ini,
+    path: &str,
+    name: Option<&str>,
+    version: u32,
+    migrate: Option<Migration>,
+    rewrite: bool,
+) -> Result<(), Error> {
+    if doc.iter().all(|(_, props)| props.is_empty()) {
+        return Ok(());
+    }
+
+    let from = self::version(doc)?;
+    if from > version {
+        return Err(Error::Migration {
+            path: path.to_string(),
+            from,
+            reason: format!("it's newer than the supported version {}", version),
+        });
+    }
+    if from == version {
+        return Ok(());
+    }
+
+    if let Some(migrate) = migrate {
+        for step in from..version {
+            migrate(doc, step).map_err(|reason| Error::Migration {
+                path: path.to_string(),
+                from: step,
+                reason,
+            })?;
+        }
+    }
+    doc.with_section(Some(SECTION))
+        .set(KEY, version.to_string());
+    diagnostics::emit(Diagnostic::Migrated {
+        path: path.to_string(),
+        from,
+        to: version,
+    });
+
+    // The migrated document is still used if it can't be written back.
+    if rewrite {
+        if let Err(e) = format::save(doc, path, name) {
+            diagnostics::emit(Diagnostic::RewriteFailed {
+                path: path.to_string(),
+                reason: e.to_string(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Not part of the public API; used by the derived code to write the
+/// version of the config file along with the struct's options.
+#[doc(hidden)]
+pub fn option(version: u32) -> OptionInfo {
+    OptionInfo {
+        field: KEY.to_string(),
+        ty: "u32".to_string(),
+        kind: ValueKind::Single,
+        help: None,
+        long: None,
+        short: None,
+        env: None,
+        section: Some(SECTION.to_string()),
+        key: Some(KEY.to_string()),
+        default: vec![version.to_string()],
+        delimiter: None,
+        possible_values: Vec::new(),
+        validation: Validation::default(),
+        secret: false,
+        aliases: Vec::new(),
+        subcommands: Vec::new(),
+    }
+}
+
+/// Not part of the public API; used by the derived code to create the
+/// missing config file at `path` with only its version.
+#[doc(hidden)]
+pub fn create(
+    path: &str,
+    name: Option<&str>,
+    mode: Option<u32>,
+    version: u32,
+) -> Result<(), Error> {
+    let mut doc = Ini::new();
+    doc.with_section(Some(SECTION))
+        .set(KEY, version.to_string());
+    format::save_with_mode(&doc, Path::new(path), name, mode)
+}
+
+/// Not part of the public API; used by the derived code to write the
+/// template of a versioned config file, which starts with its version
+/// instead of having it commented out.
+#[doc(hidden)]
+pub fn write_template(
+    options: &[OptionInfo],
+    path: &str,
+    name: Option<&str>,
+    mode: Option<u32>,
+    version: u32,
+) -> Result<(), Error> {
+    let mut template = format!("[{}]\n{} = {}\n", SECTION, KEY, version);
+    let options = meta::template(options);
+    if !options.is_empty() {
+        template.push('\n');
+        template.push_str(&options);
+    }
+    meta::save_template(&template, path, name, mode)
+}
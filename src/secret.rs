@@ -0,0 +1,33 @@
+//! Not part of the public API; helpers used by the derived code for the
+//! fields marked as `secret`.
+
+use crate::Error;
+
+use std::fmt;
+use std::fs;
+
+/// What's shown instead of the value of a secret.
+pub const REDACTED: &str = "<redacted>";
+
+/// Shown instead of a secret in the generated `Debug` implementation.
+pub struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+/// Reads the value of the secret `field` from the file at `path`, like the
+/// ones mounted by Docker or Kubernetes. The trailing newline is removed,
+/// since most editors and tools add one.
+pub fn read(field: &str, path: &str) -> Result<String, Error> {
+    let contents = fs::read_to_string(path).map_err(|source| Error::SecretFile {
+        field: field.to_string(),
+        path: path.to_string(),
+        source,
+    })?;
+    let len = contents.trim_end_matches(&['\r', '\n'][..]).len();
+
+    Ok(contents[..len].to_string())
+}
@@ -0,0 +1,122 @@
+//! Tracking where the value of each field was obtained from, which is
+//! useful to explain the final configuration to the user.
+
+use ini::Ini;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// The source a field's value was obtained from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// A command-line argument, with its name.
+    Argument { name: String },
+    /// An environment variable with the given name.
+    Env { name: String },
+    /// A key in the config file. Maps take the whole section, so they have
+    /// no key.
+    File {
+        path: String,
+        section: String,
+        key: Option<String>,
+    },
+    /// The default value of the field.
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Argument { name } => write!(f, "command-line argument `{}`", name),
+            Source::Env { name } => write!(f, "environment variable `{}`", name),
+            Source::File {
+                path,
+                section,
+                key: Some(key),
+            } => write!(f, "key `{}` in section `[{}]` of `{}`", key, section, path),
+            Source::File {
+                path,
+                section,
+                key: None,
+            } => write!(f, "section `[{}]` of `{}`", section, path),
+            Source::Default => write!(f, "default value"),
+        }
+    }
+}
+
+/// The sources of all the fields in a parsed struct, obtained with
+/// [`StructConf::parse_with_sources`](trait.StructConf.html#tymethod.parse_with_sources).
+///
+/// The fields in nested structs are named after their parent's field and
+/// their own identifier, like `database.port`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sources {
+    fields: BTreeMap<String, Source>,
+    /// The path of the config file each section and key was obtained from,
+    /// since there may be multiple layers of them. The sections are saved
+    /// without a key.
+    origins: HashMap<(String, Option<String>), String>,
+}
+
+impl Sources {
+    /// Not part of the public API; used by the derived code.
+    #[doc(hidden)]
+    pub fn new() -> Sources {
+        Sources::default()
+    }
+
+    /// Not part of the public API; used by the derived code to record that
+    /// the sections and keys in `doc` were obtained from the config file at
+    /// `path`. Later calls replace the previous ones.
+    #[doc(hidden)]
+    pub fn set_origins(&mut self, path: &str, doc: &Ini) {
+        for (section, props) in doc.iter() {
+            let section = section.unwrap_or_default().to_string();
+            self.origins
+                .insert((section.clone(), None), path.to_string());
+            for (key, _) in props.iter() {
+                self.origins
+                    .insert((section.clone(), Some(key.to_string())), path.to_string());
+            }
+        }
+    }
+
+    /// Not part of the public API; used by the derived code.
+    #[doc(hidden)]
+    pub fn set(&mut self, field: Cow<str>, source: Source) {
+        self.fields.insert(field.into_owned(), source);
+    }
+
+    /// Not part of the public API; used by the derived code to obtain the
+    /// source of a value in the config file being parsed.
+    #[doc(hidden)]
+    pub fn file(&self, section: &str, key: Option<&str>) -> Source {
+        let section = section.to_string();
+        let key = key.map(str::to_string);
+        let path = self
+            .origins
+            .get(&(section.clone(), key.clone()))
+            .cloned()
+            .unwrap_or_default();
+        Source::File { path, section, key }
+    }
+
+    /// The source of a field, if it exists.
+    pub fn source_of(&self, field: &str) -> Option<&Source> {
+        self.fields.get(field)
+    }
+
+    /// A human-readable explanation of where a field was obtained from, if
+    /// it exists.
+    pub fn explain(&self, field: &str) -> Option<String> {
+        self.source_of(field)
+            .map(|source| format!("`{}` was obtained from the {}", field, source))
+    }
+
+    /// Iterates over the fields and their sources, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Source)> {
+        self.fields
+            .iter()
+            .map(|(field, source)| (field.as_str(), source))
+    }
+}
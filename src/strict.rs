@@ -0,0 +1,130 @@
+//! Not part of the public API; helpers used by the derived code for the
+//! `deny_unknown` and `warn_unknown` attributes.
+
+use crate::diagnostics::{self, Diagnostic};
+use crate::meta::OptionInfo;
+use crate::{Error, UnknownKey};
+
+use ini::Ini;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Finds the keys and sections in the document `doc` of the config file at
+/// `path` that don't belong to any of the `options`. With `deny`, they're
+/// returned as an error. Otherwise, each of them is emitted as a
+/// [`Diagnostic`].
+pub fn check(doc: &Ini, path: &str, options: &[OptionInfo], deny: bool) -> Result<(), Error> {
+    let unknown = unknown(doc, path, options);
+    if deny {
+        return Error::from_all(unknown.into_iter().map(Error::Unknown).collect());
+    }
+
+    for unknown in unknown {
+        diagnostics::emit(Diagnostic::Unknown(unknown));
+    }
+    Ok(())
+}
+
+/// The keys and sections in `doc` unknown to the `options`. The unknown
+/// sections are reported as a whole rather than by each of their keys.
+fn unknown(doc: &Ini, path: &str, options: &[OptionInfo]) -> Vec<UnknownKey> {
+    // The known keys in each section. Maps take their whole section, so
+    // it's `None` for them. The aliases and the files of the secrets are
+    // accepted as well, but they're never suggested.
+    let mut known: BTreeMap<&str, Option<BTreeSet<&str>>> = BTreeMap::new();
+    let mut accepted = BTreeSet::new();
+    for opt in options {
+        let section = match &opt.section {
+            Some(section) => section.as_str(),
+            None => continue,
+        };
+        let keys = known
+            .entry(section)
+            .or_insert_with(|| Some(BTreeSet::new()));
+        match (&opt.key, keys) {
+            (Some(key), Some(keys)) => {
+                keys.insert(key.as_str());
+                if opt.secret {
+                    accepted.insert((section, format!("{}_file", key)));
+                }
+            }
+            (None, keys) => *keys = None,
+            _ => {}
+        }
+        for alias in &opt.aliases {
+            accepted.insert((section, alias.clone()));
+            if opt.secret {
+                accepted.insert((section, format!("{}_file", alias)));
+            }
+        }
+    }
+
+    let mut unknown = Vec::new();
+    for (section, props) in doc.iter() {
+        if props.is_empty() {
+            continue;
+        }
+        let keys = match section.map(|section| known.get(section)) {
+            Some(Some(Some(keys))) => Some(keys),
+            Some(Some(None)) => continue,
+            // The section itself is unknown.
+            Some(None) => {
+                unknown.push(UnknownKey {
+                    path: path.to_string(),
+                    section: section.map(str::to_string),
+                    key: None,
+                    suggestion: suggest(section.unwrap_or_default(), known.keys().copied()),
+                });
+                continue;
+            }
+            // The keys outside of any section.
+            None => None,
+        };
+        let mut reported = BTreeSet::new();
+        for (key, _) in props.iter() {
+            let is_known = match (section, keys) {
+                (Some(section), Some(keys)) => {
+                    keys.contains(key) || accepted.contains(&(section, key.to_string()))
+                }
+                _ => false,
+            };
+            if is_known || !reported.insert(key) {
+                continue;
+            }
+            unknown.push(UnknownKey {
+                path: path.to_string(),
+                section: section.map(str::to_string),
+                key: Some(key.to_string()),
+                suggestion: keys.and_then(|keys| suggest(key, keys.iter().copied())),
+            });
+        }
+    }
+
+    unknown
+}
+
+/// The candidate most similar to `name`, if it's close enough to be a typo.
+fn suggest<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<String> {
+    let max = name.chars().count().max(3) / 3;
+    candidates
+        .map(|candidate| (distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+/// The Levenshtein distance between two strings: the number of insertions,
+/// deletions or substitutions of characters to turn one into the other.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(ca != *cb);
+            cur.push(substitution.min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+
+    prev[b.len()]
+}
@@ -0,0 +1,66 @@
+//! Not part of the public API; helpers used by the derived code for the
+//! validation attributes. Each of them returns the reason why the value is
+//! invalid as an error.
+
+use std::fmt::Display;
+use std::ops::{Bound, RangeBounds};
+
+/// For `non_empty`, which checks the raw value before it's parsed.
+pub fn non_empty(raw: &str) -> Result<(), String> {
+    if raw.trim().is_empty() {
+        Err("the value can't be empty".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "regex")]
+pub use regex::Regex;
+#[cfg(feature = "regex")]
+use std::sync::OnceLock;
+
+/// For `regex`, which checks that the entire raw value matches the
+/// pattern. It's compiled into `cache` the first time, and its syntax was
+/// already checked by the derive macro.
+#[cfg(feature = "regex")]
+pub fn regex(cache: &OnceLock<Regex>, pattern: &str, raw: &str) -> Result<(), String> {
+    let regex = cache.get_or_init(|| {
+        Regex::new(&format!("^(?:{})$", pattern)).expect("invalid regular expression")
+    });
+    if regex.is_match(raw) {
+        Ok(())
+    } else {
+        Err(format!("the value must match `{}`", pattern))
+    }
+}
+
+/// For `range`, which checks the parsed value.
+pub fn range<T, R>(range: &R, val: &T, desc: &str) -> Result<(), String>
+where
+    T: PartialOrd,
+    R: RangeBounds<T>,
+{
+    if range.contains(val) {
+        Ok(())
+    } else {
+        Err(format!("the value must be in the range `{}`", desc))
+    }
+}
+
+/// For the information about `range` in the options, with its bounds as
+/// they would be written in the config file.
+pub fn bounds<T, R>(range: &R) -> (Bound<String>, Bound<String>)
+where
+    T: Display,
+    R: RangeBounds<T>,
+{
+    fn to_string<T: Display>(bound: Bound<&T>) -> Bound<String> {
+        match bound {
+            Bound::Included(val) => Bound::Included(val.to_string()),
+            Bound::Excluded(val) => Bound::Excluded(val.to_string()),
+            Bound::Unbounded => Bound::Unbounded,
+        }
+    }
+
+    (to_string(range.start_bound()), to_string(range.end_bound()))
+}
@@ -0,0 +1,581 @@
+//! The options to write the config file with, and the logic to edit an
+//! existing INI file while keeping its layout.
+//!
+//! Config files are always written atomically: the contents go to a
+//! temporary file in the same directory, which then replaces the original
+//! one. Thus, a crash while writing can't leave a truncated config file.
+
+use crate::meta::{OptionInfo, ValueKind};
+use crate::{format, migrate, Context, Error, StructConf};
+
+use ini::Ini;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Makes the names of the temporary files unique within the process, in
+/// case the same config file is written from multiple threads.
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The options to write the config file with
+/// [`StructConf::write_file_with`](../trait.StructConf.html#tymethod.write_file_with).
+/// They can be built by chaining its methods:
+///
+/// ```rust
+/// use structconf::WriteOptions;
+///
+/// let options = WriteOptions::new().preserve_layout(true);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteOptions {
+    mode: WriteMode,
+    preserve_layout: bool,
+    file_mode: Option<u32>,
+    secrets: bool,
+    loaded: Option<String>,
+}
+
+/// Which values are written into the config file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Every value is written, so they're pinned even if their defaults
+    /// change in the future.
+    #[default]
+    All,
+    /// Only the values that differ from their defaults are written, so that
+    /// the config file stays minimal. The rest are removed from it.
+    NonDefault,
+    /// Only the values that changed since they were loaded are written, and
+    /// the rest of the config file is kept as it was. They're compared with
+    /// the snapshot in [`WriteOptions::loaded`], or otherwise with the
+    /// values in the existing config file, or their defaults if they aren't
+    /// in it. Note that without a snapshot, the values overridden by the
+    /// arguments or the environment are written as well, since they differ
+    /// from the ones in the config file.
+    Changed,
+}
+
+impl WriteOptions {
+    /// The default options, which overwrite the entire file.
+    pub fn new() -> WriteOptions {
+        WriteOptions::default()
+    }
+
+    /// Which values are written, which is [`WriteMode::All`] by default.
+    pub fn mode(mut self, mode: WriteMode) -> WriteOptions {
+        self.mode = mode;
+        self
+    }
+
+    /// Instead of overwriting the file, only the keys owned by the struct
+    /// are updated if it already exists. The comments, blank lines, unknown
+    /// keys and the order of the existing keys are kept as they were. The
+    /// new keys are added at the end of their section, and the new sections
+    /// at the end of the file.
+    ///
+    /// This is only available for INI config files.
+    pub fn preserve_layout(mut self, preserve: bool) -> WriteOptions {
+        self.preserve_layout = preserve;
+        self
+    }
+
+    /// The Unix permissions of the config file, like `0o600`, overriding
+    /// the `file_mode` attribute of the struct. Otherwise, an existing file
+    /// keeps its permissions, and a new one is created with the default
+    /// ones. They're ignored in other platforms.
+    pub fn file_mode(mut self, mode: u32) -> WriteOptions {
+        self.file_mode = Some(mode);
+        self
+    }
+
+    /// The fields marked as `secret` are written as well. Otherwise, they
+    /// keep the value in the existing config file, if any. Unless there's a
+    /// `file_mode`, the config file is only readable by its owner when it
+    /// contains secrets.
+    pub fn secrets(mut self, write: bool) -> WriteOptions {
+        self.secrets = write;
+        self
+    }
+
+    /// Takes a snapshot of the values of `conf` right after loading it,
+    /// which [`WriteMode::Changed`] compares with. Thus, only the values
+    /// changed by the program are written, and not the ones that came from
+    /// the arguments or the environment.
+    pub fn loaded<T: StructConf>(mut self, conf: &T) -> WriteOptions {
+        let mut doc = Ini::new();
+        conf.__write(&mut doc, &Context::root());
+        let mut out = Vec::new();
+        doc.write_to(&mut out)
+            .expect("writing into memory can't fail");
+        self.loaded = Some(String::from_utf8_lossy(&out).into_owned());
+        self
+    }
+}
+
+/// Not part of the public API; used by the derived code to write the
+/// document with the values of a struct, whose options are `owned`, into
+/// the config file at `path`. `mode` is the one in the struct's `file_mode`
+/// attribute.
+#[doc(hidden)]
+pub fn save(
+    doc: &Ini,
+    owned: &[OptionInfo],
+    path: &str,
+    name: Option<&str>,
+    mode: Option<u32>,
+    options: &WriteOptions,
+) -> Result<(), Error> {
+    let has_secrets = owned.iter().any(|opt| opt.secret && opt.section.is_some());
+    let skipped = |opt: &OptionInfo| opt.secret && !options.secrets;
+    let file_mode = match (options.file_mode.or(mode), has_secrets && options.secrets) {
+        (None, true) => Some(0o600),
+        (file_mode, _) => file_mode,
+    };
+    let exists = Path::new(path).exists();
+    let preserve = options.preserve_layout && exists;
+    if preserve && !format::is_ini(Path::new(path), name) {
+        return Err(Error::Format(
+            "the layout can only be preserved in INI config files".to_string(),
+        ));
+    }
+    let skip_secrets = has_secrets && !options.secrets;
+    if options.mode == WriteMode::All && !preserve && !skip_secrets {
+        return format::save_with_mode(doc, Path::new(path), name, file_mode);
+    }
+
+    let existing = match options.mode {
+        _ if !exists => None,
+        WriteMode::Changed => Some(format::load(path, name)?),
+        _ if skip_secrets && !preserve => Some(format::load(path, name)?),
+        _ => None,
+    };
+    let loaded = match &options.loaded {
+        Some(loaded) if options.mode == WriteMode::Changed => {
+            Some(Ini::load_from_str(loaded).map_err(|e| Error::Format(e.to_string()))?)
+        }
+        _ => None,
+    };
+    let baseline = loaded.as_ref().or(existing.as_ref());
+    let actions = owned.iter().filter(|opt| opt.section.is_some()).map(|opt| {
+        if skipped(opt) {
+            (opt, Action::Keep)
+        } else {
+            (opt, action(doc, baseline, opt, options.mode))
+        }
+    });
+
+    if preserve {
+        let mut file = Document::new(&fs::read_to_string(path)?);
+        for (opt, action) in actions {
+            file.apply(doc, opt, action);
+        }
+        atomic_write(Path::new(path), file.lines.concat().as_bytes(), file_mode)?;
+    } else {
+        let mut out = match options.mode {
+            WriteMode::Changed => existing.clone().unwrap_or_default(),
+            _ => Ini::new(),
+        };
+        for (opt, action) in actions {
+            match &existing {
+                // The skipped secrets keep their value in the existing
+                // config file, which is otherwise replaced, along with the
+                // paths to the files they're read from.
+                Some(existing) if skipped(opt) => {
+                    for key in secret_keys(opt) {
+                        let opt = OptionInfo {
+                            key: Some(key),
+                            ..opt.clone()
+                        };
+                        apply(&mut out, existing, &opt, Action::Set);
+                    }
+                }
+                _ => apply(&mut out, doc, opt, action),
+            }
+        }
+        format::save_with_mode(&out, Path::new(path), name, file_mode)?;
+    }
+
+    Ok(())
+}
+
+/// The keys a secret may be read from in the config file: its own key, the
+/// one with the path to the file with its value, like `token_file`, and
+/// the same for each of its aliases.
+fn secret_keys(opt: &OptionInfo) -> Vec<String> {
+    opt.key
+        .iter()
+        .chain(&opt.aliases)
+        .flat_map(|key| vec![key.clone(), format!("{}_file", key)])
+        .collect()
+}
+
+/// What to do with an option when writing it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+    /// Leaving it as it is in the config file.
+    Keep,
+    /// Removing it from the config file.
+    Remove,
+    /// Writing its new values into the config file.
+    Set,
+}
+
+/// Decides what to do with an option, comparing its values in the
+/// struct's document `doc` with its defaults or with the ones `loaded`,
+/// depending on the mode. The version of the config file is always written,
+/// so that it's not migrated again.
+fn action(doc: &Ini, loaded: Option<&Ini>, opt: &OptionInfo, mode: WriteMode) -> Action {
+    if opt.section.as_deref() == Some(migrate::SECTION) && opt.key.as_deref() == Some(migrate::KEY)
+    {
+        return Action::Set;
+    }
+
+    let written = values(doc, opt).unwrap_or_default();
+    let is_default = || normalize(opt, written.clone()) == normalize(opt, opt.default.clone());
+    match mode {
+        WriteMode::All => Action::Set,
+        WriteMode::NonDefault if is_default() => Action::Remove,
+        WriteMode::NonDefault => Action::Set,
+        WriteMode::Changed => {
+            let unchanged = match loaded.and_then(|loaded| values(loaded, opt)) {
+                Some(loaded) => normalize(opt, loaded) == normalize(opt, written),
+                None => is_default(),
+            };
+            if unchanged {
+                Action::Keep
+            } else {
+                Action::Set
+            }
+        }
+    }
+}
+
+/// The values of an option in a document, or `None` if it's not there.
+/// These are the values of its repeated key, or for maps, the entries in
+/// its section as `key = value`, sorted like in `OptionInfo::default`.
+fn values(doc: &Ini, opt: &OptionInfo) -> Option<Vec<String>> {
+    let props = doc.section(opt.section.as_deref())?;
+    match &opt.key {
+        Some(key) if props.contains_key(key) => {
+            Some(props.get_all(key).map(str::to_string).collect())
+        }
+        Some(_) => None,
+        None => {
+            let mut entries = props
+                .iter()
+                .map(|(key, val)| format!("{} = {}", key, val))
+                .collect::<Vec<_>>();
+            entries.sort();
+            Some(entries)
+        }
+    }
+}
+
+/// Empty lists are written as an empty value, which is equivalent to not
+/// having any.
+fn normalize(opt: &OptionInfo, vals: Vec<String>) -> Vec<String> {
+    if opt.kind == ValueKind::List && vals.len() == 1 && vals[0].is_empty() {
+        Vec::new()
+    } else {
+        vals
+    }
+}
+
+/// Applies the action for an option into the document `out`, with the new
+/// values in `doc`.
+fn apply(out: &mut Ini, doc: &Ini, opt: &OptionInfo, action: Action) {
+    let section = opt.section.as_deref();
+    let props = doc.section(section);
+    match (&opt.key, action) {
+        (_, Action::Keep) => {}
+        (Some(key), _) => {
+            if let Some(props) = out.section_mut(section) {
+                props.remove_all(key).for_each(drop);
+            }
+            let vals = match (props, action) {
+                (Some(props), Action::Set) => props.get_all(key).collect(),
+                _ => Vec::new(),
+            };
+            if !vals.is_empty() {
+                let out = out
+                    .entry(section.map(str::to_string))
+                    .or_insert_with(Default::default);
+                for val in vals {
+                    out.append(key, val);
+                }
+            }
+        }
+        (None, Action::Remove) => {
+            out.delete(section);
+        }
+        (None, Action::Set) => {
+            let out = out
+                .entry(section.map(str::to_string))
+                .or_insert_with(Default::default);
+            *out = props.cloned().unwrap_or_default();
+        }
+    }
+}
+
+/// Not part of the public API; used by the derived code to create an empty
+/// config file when it's missing, with the permissions in `mode`.
+#[doc(hidden)]
+pub fn create(path: &str, mode: Option<u32>) -> io::Result<()> {
+    atomic_write(Path::new(path), &[], mode)
+}
+
+/// Writes `contents` into `path` atomically, creating its missing parent
+/// directories. The file's permissions are set to `mode` if present, and
+/// otherwise kept from the file being replaced.
+pub(crate) fn atomic_write(path: &Path, contents: &[u8], mode: Option<u32>) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid config file path: {}", path.display()),
+        )
+    })?;
+    let tmp = dir.join(format!(
+        ".{}.{}.{}.tmp",
+        name.to_string_lossy(),
+        process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = write_tmp(&tmp, path, contents, mode).and_then(|_| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return result;
+    }
+
+    // The rename itself is only durable once the directory is synced,
+    // which isn't possible in every platform.
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+
+    Ok(())
+}
+
+/// Writes the temporary file that will replace `path`, with its
+/// permissions set before any contents are written to it.
+fn write_tmp(tmp: &Path, path: &Path, contents: &[u8], mode: Option<u32>) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    // A leftover file with the same name is never reused.
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        if let Some(mode) = mode {
+            options.mode(mode);
+        }
+    }
+    let mut file = options.open(tmp)?;
+
+    match (mode, fs::metadata(path)) {
+        #[cfg(unix)]
+        (Some(mode), _) => {
+            use std::os::unix::fs::PermissionsExt;
+            // The mode in `open` is masked by the umask.
+            file.set_permissions(fs::Permissions::from_mode(mode))?;
+        }
+        (_, Ok(metadata)) => file.set_permissions(metadata.permissions())?,
+        _ => {}
+    }
+
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// An INI file as its lines, including their line endings, so that it can
+/// be edited without changing the rest of its contents.
+struct Document {
+    lines: Vec<String>,
+}
+
+/// What a line in the document contains.
+enum Line<'a> {
+    Header(&'a str),
+    Key(&'a str),
+    Other,
+}
+
+impl Document {
+    fn new(contents: &str) -> Document {
+        Document {
+            lines: contents.split_inclusive('\n').map(str::to_string).collect(),
+        }
+    }
+
+    fn parse_line(line: &str) -> Line<'_> {
+        let line = line.trim();
+        if line.starts_with('[') && line.ends_with(']') {
+            return Line::Header(line[1..line.len() - 1].trim());
+        }
+        if line.starts_with(';') || line.starts_with('#') {
+            return Line::Other;
+        }
+        match line.find(['=', ':']) {
+            Some(i) => Line::Key(line[..i].trim()),
+            None => Line::Other,
+        }
+    }
+
+    /// The indices of the section's header and of its keys, with their
+    /// names.
+    fn find_section(&self, section: &str) -> Option<(usize, Vec<(usize, String)>)> {
+        let mut header = None;
+        let mut keys = Vec::new();
+        for (i, line) in self.lines.iter().enumerate() {
+            match Document::parse_line(line) {
+                Line::Header(name) if header.is_some() && name != section => break,
+                Line::Header(name) if name == section => header = Some(i),
+                Line::Key(key) if header.is_some() => keys.push((i, key.to_string())),
+                _ => {}
+            }
+        }
+
+        header.map(|header| (header, keys))
+    }
+
+    /// Applies the action for an option into the document, with the new
+    /// values in `doc`.
+    fn apply(&mut self, doc: &Ini, opt: &OptionInfo, action: Action) {
+        let section = match &opt.section {
+            Some(section) => section.as_str(),
+            None => return,
+        };
+        let props = doc.section(Some(section));
+        match (&opt.key, action) {
+            (_, Action::Keep) => {}
+            (Some(key), _) => {
+                let vals: Vec<&str> = match (props, action) {
+                    (Some(props), Action::Set) => props.get_all(key).collect(),
+                    _ => Vec::new(),
+                };
+                self.set(section, key, &vals);
+            }
+            // Maps own their entire section.
+            (None, Action::Remove) => self.remove_section(section),
+            (None, Action::Set) => {
+                self.add_section(section);
+                let props = match props {
+                    Some(props) => props,
+                    None => return,
+                };
+                self.retain(section, |key| props.contains_key(key));
+                for (key, _) in props.iter() {
+                    let vals = props.get_all(key).collect::<Vec<_>>();
+                    self.set(section, key, &vals);
+                }
+            }
+        }
+    }
+
+    /// Removes a section with all of its lines, up to the next header.
+    fn remove_section(&mut self, section: &str) {
+        let header = match self.find_section(section) {
+            Some((header, _)) => header,
+            None => return,
+        };
+        let end = self.lines[header + 1..]
+            .iter()
+            .position(|line| matches!(Document::parse_line(line), Line::Header(_)))
+            .map(|pos| header + 1 + pos)
+            .unwrap_or_else(|| self.lines.len());
+        self.lines.drain(header..end);
+    }
+
+    /// Adds an empty section at the end of the document if it doesn't exist.
+    fn add_section(&mut self, section: &str) {
+        if self.find_section(section).is_some() {
+            return;
+        }
+
+        match self.lines.last_mut() {
+            Some(last) if !last.ends_with('\n') => last.push('\n'),
+            _ => {}
+        }
+        if !self.lines.is_empty() {
+            self.lines.push("\n".to_string());
+        }
+        self.lines.push(format!("[{}]\n", section));
+    }
+
+    /// Removes the keys in the section for which `keep` returns `false`.
+    fn retain(&mut self, section: &str, keep: impl Fn(&str) -> bool) {
+        if let Some((_, keys)) = self.find_section(section) {
+            for (i, _) in keys.iter().rev().filter(|(_, key)| !keep(key)) {
+                self.lines.remove(*i);
+            }
+        }
+    }
+
+    /// Sets the values of a key, which may be repeated for lists. The
+    /// existing lines are updated in place, keeping what's before the value,
+    /// and the surplus ones are removed. No values remove the key.
+    fn set(&mut self, section: &str, key: &str, vals: &[&str]) {
+        if !vals.is_empty() {
+            self.add_section(section);
+        }
+        let (header, keys) = match self.find_section(section) {
+            Some(found) => found,
+            None => return,
+        };
+        let existing = keys
+            .iter()
+            .filter(|(_, name)| name == key)
+            .map(|(i, _)| *i)
+            .collect::<Vec<_>>();
+
+        for (i, val) in existing.iter().zip(vals) {
+            let line = &self.lines[*i];
+            let sep = line.find(['=', ':']).unwrap();
+            let value_start =
+                sep + 1 + (line[sep + 1..].len() - line[sep + 1..].trim_start().len());
+            let ending = if line.ends_with("\r\n") { "\r\n" } else { "\n" };
+            self.lines[*i] = format!("{}{}{}", &line[..value_start], escape(val), ending);
+        }
+        for i in existing.iter().skip(vals.len()).rev() {
+            self.lines.remove(*i);
+        }
+
+        // The new values are inserted after the last key in the section, or
+        // after its header if it's empty.
+        if vals.len() > existing.len() {
+            let pos = match (existing.last(), keys.last()) {
+                (Some(i), _) | (None, Some((i, _))) => i + 1,
+                (None, None) => header + 1,
+            };
+            if let Some(line) = self.lines.get_mut(pos - 1) {
+                if !line.ends_with('\n') {
+                    line.push('\n');
+                }
+            }
+            for (offset, val) in vals[existing.len()..].iter().enumerate() {
+                let line = format!("{}={}\n", escape(key), escape(val));
+                self.lines.insert(pos + offset, line);
+            }
+        }
+    }
+}
+
+/// Escapes a key or a value the same way `rust-ini` does when writing.
+fn escape(val: &str) -> String {
+    let mut doc = Ini::new();
+    doc.with_general_section().set("k", val);
+    let mut buf = Vec::new();
+    doc.write_to(&mut buf).unwrap();
+    let line = String::from_utf8_lossy(&buf);
+    let line = line.trim_end_matches(['\r', '\n']);
+    // Only the key written above is removed, since the value itself may
+    // start the same way.
+    line.strip_prefix("k=").unwrap_or(line).to_string()
+}
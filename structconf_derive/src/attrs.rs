@@ -1,21 +1,245 @@
 //! The basic structure for the field's data, containing all the available
-//! attributes in `#[conf(...)]` and some additional contents.
+//! attributes in `#[conf(...)]` and some additional contents. The attributes
+//! for the struct itself are also defined here.
 
 use crate::error::{Error, ErrorKind, Result};
-use crate::opt::{Opt, OptArgData, OptBaseData, OptFileData, OptKind};
+use crate::opt::{
+    Opt, OptArgData, OptBaseData, OptEnvData, OptFileData, OptKind, OptNestedData, OptSectionData,
+    OptValidationData,
+};
 
-use darling::FromField;
+use darling::{FromDeriveInput, FromField, FromVariant};
+use quote::quote;
 use std::rc::Rc;
-use syn::{spanned::Spanned, Field, Ident, Path, Type, TypePath};
+use syn::{
+    spanned::Spanned, Attribute, Expr, ExprRange, Field, Fields, Ident, Meta, NestedMeta, Path,
+    RangeLimits, Type, TypePath, Variant,
+};
+
+/// The attributes available in `#[conf(...)]` for the derived struct
+/// itself, which apply to all of its fields.
+#[derive(FromDeriveInput)]
+#[darling(attributes(conf))]
+pub struct StructAttrs {
+    pub ident: Ident,
+    #[darling(default)]
+    pub env_prefix: Option<String>,
+    #[darling(default)]
+    pub format: Option<String>,
+    #[darling(default)]
+    pub missing_file: Option<String>,
+    #[darling(default)]
+    pub file_mode: Option<u32>,
+    #[darling(default)]
+    pub version: Option<u32>,
+    #[darling(default)]
+    pub migrate: Option<String>,
+    #[darling(default)]
+    pub rewrite_migrated: bool,
+    #[darling(default)]
+    pub deny_unknown: bool,
+    #[darling(default)]
+    pub warn_unknown: bool,
+}
+
+impl StructAttrs {
+    /// Checks that the values of the attributes are valid.
+    pub fn check_values(&self) -> Result<()> {
+        // The same names are accepted by `structconf::format::from_name`.
+        const FORMATS: &[&str] = &["ini", "toml", "json", "yaml", "yml"];
+        if let Some(format) = &self.format {
+            if !FORMATS.contains(&format.as_str()) {
+                return Err(Error {
+                    span: self.ident.span(),
+                    kind: ErrorKind::Value(
+                        "format".to_string(),
+                        format!("expected one of {}", FORMATS.join(", ")),
+                    ),
+                });
+            }
+        }
+
+        const MISSING_FILE: &[&str] = &["create", "template", "empty", "error"];
+        if let Some(missing_file) = &self.missing_file {
+            if !MISSING_FILE.contains(&missing_file.as_str()) {
+                return Err(Error {
+                    span: self.ident.span(),
+                    kind: ErrorKind::Value(
+                        "missing_file".to_string(),
+                        format!("expected one of {}", MISSING_FILE.join(", ")),
+                    ),
+                });
+            }
+        }
+
+        if let Some(mode) = self.file_mode {
+            if mode > 0o7777 {
+                return Err(Error {
+                    span: self.ident.span(),
+                    kind: ErrorKind::Value(
+                        "file_mode".to_string(),
+                        "expected Unix permissions, like 0o600".to_string(),
+                    ),
+                });
+            }
+        }
+
+        if self.deny_unknown && self.warn_unknown {
+            return Err(Error {
+                span: self.ident.span(),
+                kind: ErrorKind::ConflictAttrs(
+                    "deny_unknown".to_string(),
+                    "warn_unknown".to_string(),
+                ),
+            });
+        }
+
+        if self.version == Some(0) {
+            return Err(Error {
+                span: self.ident.span(),
+                kind: ErrorKind::Value("version".to_string(), "versions start at 1".to_string()),
+            });
+        }
+        if self.version.is_none() {
+            for (present, attr) in [
+                (self.migrate.is_some(), "migrate"),
+                (self.rewrite_migrated, "rewrite_migrated"),
+            ] {
+                if present {
+                    return Err(Error {
+                        span: self.ident.span(),
+                        kind: ErrorKind::Value(
+                            attr.to_string(),
+                            "it requires a `version`".to_string(),
+                        ),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Rejects the attributes in `#[conf(...)]` on top of an enum. Enums are
+/// embedded into a struct as subcommands, so the struct attributes would be
+/// silently ignored.
+pub fn check_enum_attrs(attrs: &[Attribute]) -> darling::Result<()> {
+    let mut errors = darling::Error::accumulator();
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("conf")) {
+        let list = match attr.parse_meta() {
+            Ok(Meta::List(list)) => list,
+            Ok(meta) => {
+                errors.push(darling::Error::unsupported_format("non-list").with_span(&meta));
+                continue;
+            }
+            Err(e) => {
+                errors.push(e.into());
+                continue;
+            }
+        };
+        for nested in &list.nested {
+            let name = match nested {
+                NestedMeta::Meta(meta) => {
+                    let path = meta.path();
+                    quote!(#path).to_string()
+                }
+                NestedMeta::Lit(lit) => quote!(#lit).to_string(),
+            };
+            errors.push(
+                darling::Error::custom(format!(
+                    "`{}` isn't available for enums, it should be used in the \
+                    struct that embeds them as a subcommand",
+                    name
+                ))
+                .with_span(nested),
+            );
+        }
+    }
+    errors.finish()
+}
+
+/// The attributes available in `#[conf(...)]` for the variants of an enum,
+/// which are subcommands.
+#[derive(FromVariant)]
+#[darling(attributes(conf))]
+pub struct VariantAttrs {
+    pub ident: Ident,
+    /// The type of the struct in the variant.
+    #[darling(skip)]
+    pub ty: Option<Type>,
+    #[darling(default)]
+    pub name: Option<String>,
+    #[darling(default)]
+    pub section: Option<String>,
+    #[darling(default)]
+    pub help: Option<String>,
+}
+
+impl VariantAttrs {
+    /// Initializes the attributes of a variant, which must contain a single
+    /// unnamed field with the subcommand's struct.
+    pub fn init(variant: &Variant) -> Result<VariantAttrs> {
+        let mut attrs = VariantAttrs::from_variant(variant)?;
+        match &variant.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                attrs.ty = Some(fields.unnamed[0].ty.clone());
+            }
+            Fields::Unit => {
+                return Err(Error {
+                    span: variant.ident.span(),
+                    kind: ErrorKind::DeriveType("unit variant".to_string()),
+                })
+            }
+            _ => {
+                return Err(Error {
+                    span: variant.ident.span(),
+                    kind: ErrorKind::DeriveType("variant with multiple fields".to_string()),
+                })
+            }
+        }
+
+        Ok(attrs)
+    }
+
+    /// The name of the subcommand, which is the variant's identifier in
+    /// kebab case by default.
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            let mut name = String::new();
+            for (i, c) in self.ident.to_string().chars().enumerate() {
+                if c.is_uppercase() && i > 0 {
+                    name.push('-');
+                }
+                name.extend(c.to_lowercase());
+            }
+            name
+        })
+    }
+
+    /// The section of the subcommand's options in the config file, which is
+    /// its name by default.
+    pub fn section(&self) -> String {
+        self.section.clone().unwrap_or_else(|| self.name())
+    }
+}
 
 #[derive(FromField)]
 #[darling(attributes(conf))]
 pub struct Attrs {
     pub ident: Option<Ident>,
     pub ty: Type,
+    /// The original type of the field, since `ty` is replaced by the inner
+    /// one in `Option<T>`, lists and maps.
+    #[darling(skip)]
+    pub field_ty: Option<Type>,
     #[darling(skip)]
     pub is_option: bool,
     #[darling(skip)]
+    pub is_vec: bool,
+    #[darling(skip)]
+    pub map_key: Option<Type>,
+    #[darling(skip)]
     pub takes_value: bool,
     #[darling(default)]
     pub default: Option<String>,
@@ -37,6 +261,38 @@ pub struct Attrs {
     pub file: Option<String>,
     #[darling(default)]
     pub section: Option<String>,
+    #[darling(default)]
+    pub no_env: bool,
+    #[darling(default)]
+    pub env: Option<String>,
+    #[darling(default)]
+    pub flatten: bool,
+    #[darling(default)]
+    pub nested: bool,
+    #[darling(default)]
+    pub subcommand: bool,
+    #[darling(default)]
+    pub delimiter: Option<String>,
+    #[darling(default)]
+    pub section_map: Option<String>,
+    #[darling(default)]
+    pub range: Option<String>,
+    #[darling(default)]
+    pub regex: Option<String>,
+    #[darling(default)]
+    pub non_empty: bool,
+    #[darling(default)]
+    pub validate: Option<String>,
+    #[darling(default)]
+    pub possible_values: Option<String>,
+    #[darling(default)]
+    pub secret: bool,
+    #[darling(multiple)]
+    pub alias: Vec<String>,
+    #[darling(default)]
+    pub deprecated: Option<String>,
+    #[darling(default)]
+    pub required: bool,
 }
 
 impl Attrs {
@@ -44,49 +300,62 @@ impl Attrs {
     /// done by `darling`, and some extra checks for conflicts and for the
     /// type. The `Attrs::from_field` method generated by `darling`
     /// shouldn't be used by itself.
-    pub fn init(field: Field) -> Result<Attrs> {
+    pub fn init(field: Field, parent: &StructAttrs) -> Result<Attrs> {
         let mut attrs = Attrs::from_field(&field)?;
         attrs.apply_rules();
         attrs.check_conflicts()?;
+        attrs.apply_parent(parent);
 
         Ok(attrs)
     }
 
     /// Applies some rules to itself depending on its attributes.
     fn apply_rules(&mut self) {
-        // Painfully obtaining the type `T` inside `Option<T>` to assign
-        // `is_optional`.
-        if let Type::Path(TypePath {
-            path: Path { segments, .. },
-            ..
-        }) = &self.ty
+        self.field_ty = Some(self.ty.clone());
+
+        // Obtaining the type `T` inside `Option<T>` or `Vec<T>` to assign
+        // `is_option` or `is_vec`. For maps, the types of both the keys and
+        // the values are obtained.
+        if let Some(ty) = inner_type(&self.ty, "Option") {
+            self.ty = ty;
+            self.is_option = true;
+        } else if let Some(ty) = inner_type(&self.ty, "Vec") {
+            self.ty = ty;
+            self.is_vec = true;
+        } else if let Some((key, val)) =
+            map_types(&self.ty, "HashMap").or_else(|| map_types(&self.ty, "BTreeMap"))
         {
-            if segments.len() == 1 && segments.first().unwrap().ident == "Option" {
-                let args = &segments.first().unwrap().arguments;
-                use syn::{
-                    AngleBracketedGenericArguments as Brackets, GenericArgument::Type as InnerType,
-                    PathArguments::AngleBracketed as PathAngles,
-                };
-
-                // Obtaining the type inside the `Option<T>`.
-                if let PathAngles(Brackets { args, .. }) = args {
-                    if let InnerType(ty) = args.first().unwrap() {
-                        self.ty = ty.clone();
-                        self.is_option = true;
-                    }
-                }
-            }
+            self.ty = val;
+            self.map_key = Some(key);
         }
 
-        // Only boolean flags won't take value.
-        self.takes_value = true;
-        if let Type::Path(TypePath {
-            path: Path { segments, .. },
-            ..
-        }) = &self.ty
-        {
-            if segments.len() == 1 && segments.first().unwrap().ident == "bool" {
-                self.takes_value = false;
+        // Only boolean flags won't take value. Lists always take value,
+        // even if they're made up of booleans.
+        self.takes_value = self.is_vec || self.map_key.is_some() || !is_type(&self.ty, "bool");
+
+        // Markers like `PhantomData<T>` in generic structs aren't options.
+        if ends_with_type(&self.ty, "PhantomData") {
+            self.no_short = true;
+            self.no_long = true;
+            self.no_file = true;
+        }
+    }
+
+    /// Applies the rules inherited from the struct's attributes. This must
+    /// be done after checking the conflicts, since the inherited values
+    /// weren't explicitly written by the user.
+    fn apply_parent(&mut self, parent: &StructAttrs) {
+        // Skipped fields don't inherit an environment variable, since they
+        // aren't meant to be configurable. Neither do nested structs, whose
+        // fields have their own environment variables.
+        // Maps can't be read from the environment either.
+        let skipped = self.no_short && self.no_long && self.no_file;
+        let nested = self.flatten || self.nested || self.subcommand;
+        let map = self.map_key.is_some();
+        if self.env.is_none() && !self.no_env && !skipped && !nested && !map {
+            if let Some(prefix) = &parent.env_prefix {
+                let ident = self.ident.as_ref().unwrap().to_string();
+                self.env = Some(format!("{}{}", prefix, ident.to_uppercase()));
             }
         }
     }
@@ -127,14 +396,23 @@ impl Attrs {
                 (self.negated_arg, "negated_arg"),
                 (self.file.is_some(), "file"),
                 (self.section.is_some(), "section"),
+                (self.no_env, "no_env"),
+                (self.secret, "secret"),
+                (!self.alias.is_empty(), "alias"),
+                (self.deprecated.is_some(), "deprecated"),
+                (self.required, "required"),
             ]
         );
 
+        // The help message is also used in the config file templates, so it's
+        // only out of place for fields that are neither arguments nor in the
+        // config file.
         check_conflicts!(
             (self.no_short && self.no_long, "no_short and no_long"),
             [
                 (self.negated_arg, "negated_arg"),
-                (self.help.is_some(), "help"),
+                (self.no_file && self.help.is_some(), "help"),
+                (self.possible_values.is_some(), "possible_values"),
             ]
         );
 
@@ -161,6 +439,163 @@ impl Attrs {
             ]
         );
 
+        check_conflicts!((self.no_env, "no_env"), [(self.env.is_some(), "env"),]);
+
+        check_conflicts!(
+            (self.possible_values.is_some(), "possible_values"),
+            [(!self.takes_value, "field's type"),]
+        );
+
+        // Aliases are only available for the long argument and the key in
+        // the config file.
+        check_conflicts!(
+            (!self.alias.is_empty(), "alias"),
+            [
+                (self.no_long && self.no_file, "no_long and no_file"),
+                (self.section_map.is_some(), "section_map"),
+                (self.flatten, "flatten"),
+                (self.nested, "nested"),
+                (self.subcommand, "subcommand"),
+            ]
+        );
+
+        check_conflicts!(
+            (self.deprecated.is_some(), "deprecated"),
+            [
+                (self.flatten, "flatten"),
+                (self.nested, "nested"),
+                (self.subcommand, "subcommand"),
+            ]
+        );
+
+        // Required fields must be given a value by the user, so they can't
+        // fall back to anything.
+        check_conflicts!(
+            (self.required, "required"),
+            [
+                (self.is_option, "Option<T>"),
+                (self.default.is_some(), "default"),
+                (self.negated_arg, "negated_arg"),
+                (self.flatten, "flatten"),
+                (self.nested, "nested"),
+                (self.subcommand, "subcommand"),
+            ]
+        );
+
+        // Secrets are read as a whole from their files, so they must be a
+        // single value.
+        check_conflicts!(
+            (self.secret, "secret"),
+            [
+                (!self.takes_value, "field's type"),
+                (self.is_vec, "Vec<T>"),
+                (self.map_key.is_some(), "field's type"),
+                (self.flatten, "flatten"),
+                (self.nested, "nested"),
+                (self.subcommand, "subcommand"),
+            ]
+        );
+
+        check_conflicts!(
+            (self.delimiter.is_some(), "delimiter"),
+            [(!self.is_vec, "field's type"),]
+        );
+
+        // Maps are always in their own section in the config file.
+        check_conflicts!(
+            (self.section_map.is_some(), "section_map"),
+            [
+                (self.map_key.is_none(), "field's type"),
+                (self.no_file, "no_file"),
+                (self.file.is_some(), "file"),
+                (self.section.is_some(), "section"),
+            ]
+        );
+
+        check_conflicts!(
+            (self.env.is_some(), "env"),
+            [(self.map_key.is_some(), "field's type"),]
+        );
+
+        if self.map_key.is_some() && self.section_map.is_none() && !self.no_file {
+            return Err(Error {
+                span: self.ident.span(),
+                kind: ErrorKind::Parse(
+                    "maps require `section_map` to be available in the config file, or \
+                    `no_file`"
+                        .to_string(),
+                ),
+            });
+        }
+
+        // Nested structs may only customize their section and the prefix
+        // for their arguments, and flattened structs can't customize
+        // anything.
+        check_conflicts!(
+            (
+                self.flatten || self.nested,
+                if self.flatten { "flatten" } else { "nested" }
+            ),
+            [
+                (self.is_option, "Option<T>"),
+                (self.default.is_some(), "default"),
+                (self.no_long, "no_long"),
+                (self.no_short, "no_short"),
+                (self.short.is_some(), "short"),
+                (self.help.is_some(), "help"),
+                (self.negated_arg, "negated_arg"),
+                (self.no_file, "no_file"),
+                (self.file.is_some(), "file"),
+                (self.no_env, "no_env"),
+                (self.env.is_some(), "env"),
+                (self.delimiter.is_some(), "delimiter"),
+                (self.section_map.is_some(), "section_map"),
+                (self.range.is_some(), "range"),
+                (self.regex.is_some(), "regex"),
+                (self.non_empty, "non_empty"),
+                (self.validate.is_some(), "validate"),
+                (self.possible_values.is_some(), "possible_values"),
+            ]
+        );
+
+        // Subcommands can't customize anything, since their options are
+        // configured in each variant.
+        check_conflicts!(
+            (self.subcommand, "subcommand"),
+            [
+                (self.flatten, "flatten"),
+                (self.nested, "nested"),
+                (self.is_vec, "Vec<T>"),
+                (self.default.is_some(), "default"),
+                (self.long.is_some(), "long"),
+                (self.no_long, "no_long"),
+                (self.short.is_some(), "short"),
+                (self.no_short, "no_short"),
+                (self.help.is_some(), "help"),
+                (self.negated_arg, "negated_arg"),
+                (self.no_file, "no_file"),
+                (self.file.is_some(), "file"),
+                (self.section.is_some(), "section"),
+                (self.no_env, "no_env"),
+                (self.env.is_some(), "env"),
+                (self.delimiter.is_some(), "delimiter"),
+                (self.range.is_some(), "range"),
+                (self.regex.is_some(), "regex"),
+                (self.non_empty, "non_empty"),
+                (self.validate.is_some(), "validate"),
+                (self.possible_values.is_some(), "possible_values"),
+            ]
+        );
+
+        check_conflicts!(
+            (self.flatten, "flatten"),
+            [
+                (self.nested, "nested"),
+                (self.long.is_some(), "long"),
+                (self.section.is_some(), "section"),
+            ]
+        );
+
         Ok(())
     }
 
@@ -174,6 +609,7 @@ impl Attrs {
                 .section
                 .clone()
                 .unwrap_or_else(|| "Defaults".to_string()),
+            alias: false,
         }
     }
 
@@ -193,27 +629,9 @@ impl Attrs {
             None
         } else {
             match &self.short {
-                Some(s) => {
-                    // If the user provides the short name, this makes
-                    // sure it's a single character.
-                    let mut chars = s.chars();
-                    let first = chars.next();
-                    let second = chars.next();
-
-                    match (first, second) {
-                        (Some(ch), None) => Some(ch),
-                        _ => {
-                            return Err(Error {
-                                span: self.ident.span(),
-                                kind: ErrorKind::Parse(
-                                    "short argument can't be longer than \
-                                    one character"
-                                        .to_string(),
-                                ),
-                            })
-                        }
-                    }
-                }
+                // If the user provides the short name, this makes sure it's
+                // a single character.
+                Some(s) => Some(self.parse_char(s, "short argument")?),
                 None => {
                     // Otherwise, the short name is obtained from the
                     // identifier, which must be at least a character
@@ -223,67 +641,303 @@ impl Attrs {
             }
         };
 
+        let possible_values = match &self.possible_values {
+            Some(values) => Some(syn::parse_str::<Expr>(values)?),
+            None => None,
+        };
+
         Ok(OptArgData {
             long,
             short,
             help: self.help.clone(),
             negated: self.negated_arg,
+            possible_values,
+            alias: false,
+        })
+    }
+
+    /// Makes sure that the value of an attribute is a single character.
+    fn parse_char(&self, value: &str, desc: &str) -> Result<char> {
+        let mut chars = value.chars();
+        let first = chars.next();
+        let second = chars.next();
+
+        match (first, second) {
+            (Some(ch), None) => Ok(ch),
+            _ => Err(Error {
+                span: self.ident.span(),
+                kind: ErrorKind::Parse(format!("{} can't be longer than one character", desc)),
+            }),
+        }
+    }
+
+    /// Makes sure that the value of `regex` is a valid regular expression,
+    /// which requires the `regex` feature of structconf.
+    #[cfg(feature = "regex")]
+    fn parse_regex(&self, value: &str) -> Result<String> {
+        match regex::Regex::new(value) {
+            Ok(_) => Ok(value.to_string()),
+            Err(e) => Err(Error {
+                span: self.ident.span(),
+                kind: ErrorKind::Value("regex".to_string(), e.to_string()),
+            }),
+        }
+    }
+
+    #[cfg(not(feature = "regex"))]
+    fn parse_regex(&self, _value: &str) -> Result<String> {
+        Err(Error {
+            span: self.ident.span(),
+            kind: ErrorKind::Parse(
+                "`regex` requires enabling the `regex` cargo feature of structconf".to_string(),
+            ),
         })
     }
 
-    /// Parses the attributes into an option, which may be empty, an argument,
-    /// a file, or both. Thus, two options are returned in case it's both.
-    pub fn parse_opt(self) -> Result<(Opt, Option<Opt>)> {
+    /// Makes sure that the value of `range` is a valid range expression.
+    fn parse_range(&self, value: &str) -> Result<Expr> {
+        let expr = syn::parse_str::<Expr>(value)?;
+        // Inclusive ranges need an end, and `..` wouldn't check anything.
+        let valid = match &expr {
+            Expr::Range(ExprRange {
+                from, limits, to, ..
+            }) => to.is_some() || (from.is_some() && matches!(limits, RangeLimits::HalfOpen(_))),
+            _ => false,
+        };
+        if valid {
+            return Ok(expr);
+        }
+
+        Err(Error {
+            span: self.ident.span(),
+            kind: ErrorKind::Value(
+                "range".to_string(),
+                "expected a bounded range like `1..=10` or `0..`".to_string(),
+            ),
+        })
+    }
+
+    pub fn get_env_data(&self) -> Option<OptEnvData> {
+        self.env.clone().map(|name| OptEnvData { name })
+    }
+
+    /// Parses the attributes into the options a field is made up of, which
+    /// may be empty, an argument, an environment variable, a file, or a
+    /// combination of them. They are returned in order of priority.
+    pub fn parse_opt(self) -> Result<Vec<Opt>> {
+        let delimiter = match &self.delimiter {
+            Some(d) => Some(self.parse_char(d, "delimiter")?),
+            None => None,
+        };
+        let validation = OptValidationData {
+            range: match &self.range {
+                Some(range) => Some(self.parse_range(range)?),
+                None => None,
+            },
+            regex: match &self.regex {
+                Some(regex) => Some(self.parse_regex(regex)?),
+                None => None,
+            },
+            non_empty: self.non_empty,
+            validate: match &self.validate {
+                Some(validate) => Some(syn::parse_str::<Path>(validate)?),
+                None => None,
+            },
+        };
         let base = Rc::new(OptBaseData {
             is_option: self.is_option,
+            is_vec: self.is_vec,
+            map_key: self.map_key.clone(),
+            delimiter,
+            is_path: ends_with_type(&self.ty, "PathBuf"),
+            secret: self.secret,
+            aliases: self.alias.clone(),
+            deprecated: self.deprecated.clone(),
+            required: self.required,
+            validation,
             default: self.default.clone(),
             id: self.ident.clone().unwrap(),
             ty: self.ty.clone(),
+            field_ty: self.field_ty.clone().unwrap(),
+            help: self.help.clone(),
         });
 
+        if self.flatten {
+            return Ok(vec![Opt {
+                base,
+                kind: OptKind::Flatten,
+            }]);
+        }
+        if self.subcommand {
+            return Ok(vec![Opt {
+                base,
+                kind: OptKind::Subcommand,
+            }]);
+        }
+        if self.nested {
+            let ident = self.ident.as_ref().unwrap().to_string();
+            let data = OptNestedData {
+                prefix: self.long.clone().unwrap_or_else(|| ident.clone()),
+                section: self.section.clone().unwrap_or(ident),
+            };
+            return Ok(vec![Opt {
+                base,
+                kind: OptKind::Nested(data),
+            }]);
+        }
+
         let arg_kind = if self.takes_value {
             OptKind::Arg
         } else {
             OptKind::Flag
         };
 
-        let ret = if self.no_long && self.no_short && self.no_file {
-            (
-                Opt {
-                    base,
-                    kind: OptKind::Empty,
-                },
-                None,
-            )
-        } else if self.no_file {
-            (
-                Opt {
-                    base,
-                    kind: arg_kind(self.get_arg_data()?),
-                },
-                None,
-            )
-        } else if self.no_long && self.no_short {
-            (
-                Opt {
-                    base,
-                    kind: OptKind::File(self.get_file_data()),
-                },
-                None,
-            )
-        } else {
-            (
-                Opt {
+        // The aliases follow the option they belong to, which takes
+        // priority over them. They're hidden long arguments and extra keys
+        // in the config file.
+        let mut opts = Vec::new();
+        if !(self.no_long && self.no_short) {
+            let arg = self.get_arg_data()?;
+            let mut args = Vec::new();
+            if !self.no_long {
+                for alias in &self.alias {
+                    args.push(OptArgData {
+                        long: Some(alias.replace('_', "-")),
+                        short: None,
+                        help: None,
+                        negated: arg.negated,
+                        possible_values: arg.possible_values.clone(),
+                        alias: true,
+                    });
+                }
+            }
+            args.insert(0, arg);
+            for arg in args {
+                opts.push(Opt {
                     base: Rc::clone(&base),
-                    kind: arg_kind(self.get_arg_data()?),
-                },
-                Some(Opt {
-                    base,
-                    kind: OptKind::File(self.get_file_data()),
+                    kind: arg_kind(arg),
+                });
+            }
+        }
+        if let Some(env) = self.get_env_data() {
+            opts.push(Opt {
+                base: Rc::clone(&base),
+                kind: OptKind::Env(env),
+            });
+        }
+        if let Some(section) = &self.section_map {
+            opts.push(Opt {
+                base: Rc::clone(&base),
+                kind: OptKind::Section(OptSectionData {
+                    section: section.clone(),
                 }),
-            )
-        };
+            });
+        } else if !self.no_file {
+            let file = self.get_file_data();
+            let mut files = Vec::new();
+            for alias in &self.alias {
+                files.push(OptFileData {
+                    name: alias.clone(),
+                    section: file.section.clone(),
+                    alias: true,
+                });
+            }
+            files.insert(0, file);
+            for file in files {
+                opts.push(Opt {
+                    base: Rc::clone(&base),
+                    kind: OptKind::File(file),
+                });
+            }
+        }
+        if opts.is_empty() {
+            opts.push(Opt {
+                base,
+                kind: OptKind::Empty,
+            });
+        }
+
+        Ok(opts)
+    }
+}
+
+/// Painfully obtains the type `T` inside a type like `Option<T>`, given the
+/// name of the wrapper. It must be written exactly like that; for example,
+/// `std::option::Option<T>` won't work.
+fn inner_type(ty: &Type, wrapper: &str) -> Option<Type> {
+    use syn::{
+        AngleBracketedGenericArguments as Brackets, GenericArgument::Type as InnerType,
+        PathArguments::AngleBracketed as PathAngles,
+    };
+
+    if let Type::Path(TypePath {
+        path: Path { segments, .. },
+        ..
+    }) = ty
+    {
+        if segments.len() == 1 && segments.first().unwrap().ident == wrapper {
+            let args = &segments.first().unwrap().arguments;
+            if let PathAngles(Brackets { args, .. }) = args {
+                if let Some(InnerType(ty)) = args.first() {
+                    return Some(ty.clone());
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Obtains the types `K` and `V` inside a map type like `HashMap<K, V>`,
+/// given the name of the map.
+fn map_types(ty: &Type, map: &str) -> Option<(Type, Type)> {
+    use syn::{
+        AngleBracketedGenericArguments as Brackets, GenericArgument::Type as InnerType,
+        PathArguments::AngleBracketed as PathAngles,
+    };
+
+    if let Type::Path(TypePath {
+        path: Path { segments, .. },
+        ..
+    }) = ty
+    {
+        if segments.len() == 1 && segments.first().unwrap().ident == map {
+            let args = &segments.first().unwrap().arguments;
+            if let PathAngles(Brackets { args, .. }) = args {
+                let mut args = args.iter();
+                if let (Some(InnerType(key)), Some(InnerType(val))) = (args.next(), args.next()) {
+                    return Some((key.clone(), val.clone()));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Checks if the type is exactly the one with the given name.
+fn is_type(ty: &Type, name: &str) -> bool {
+    if let Type::Path(TypePath {
+        path: Path { segments, .. },
+        ..
+    }) = ty
+    {
+        segments.len() == 1 && segments.first().unwrap().ident == name
+    } else {
+        false
+    }
+}
 
-        Ok(ret)
+/// Checks if the type has the given name, written by itself or with its full
+/// path, like `PathBuf` and `std::path::PathBuf`.
+fn ends_with_type(ty: &Type, name: &str) -> bool {
+    if let Type::Path(TypePath {
+        path: Path { segments, .. },
+        ..
+    }) = ty
+    {
+        segments.last().map_or(false, |seg| seg.ident == name)
+    } else {
+        false
     }
 }
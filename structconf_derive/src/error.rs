@@ -27,7 +27,8 @@ impl fmt::Display for Error {
             DeriveType(ty) => write!(
                 f,
                 "Cannot #[derive(StructConf)] for the type `{}`, StructConf \
-                is only available for named structs.",
+                is only available for named structs, and for enums whose \
+                variants contain a single struct.",
                 ty
             ),
             ConflictAttrs(opt1, opt2) => write!(f, "`{}` is incompatible with `{}`.", opt1, opt2),
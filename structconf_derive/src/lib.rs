@@ -8,15 +8,22 @@ mod attrs;
 mod error;
 mod opt;
 
-use crate::attrs::Attrs;
+use crate::attrs::{Attrs, StructAttrs, VariantAttrs};
 use crate::error::{Error, ErrorKind, Result};
-use crate::opt::{Opt, OptKind};
+use crate::opt::{
+    gen_bounds, gen_missing, gen_option_info, gen_owned_options, Opt, OptBaseData, OptKind,
+};
 
+use darling::FromDeriveInput;
 use proc_macro::TokenStream;
-use proc_macro2::Ident;
-use quote::quote;
+use proc_macro2::{Ident, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
 use std::collections::HashSet;
-use syn::{Data, DataStruct, DeriveInput, Fields, FieldsNamed};
+use std::rc::Rc;
+use syn::spanned::Spanned;
+use syn::{
+    parse_quote_spanned, Data, DataEnum, DataStruct, DeriveInput, Fields, FieldsNamed, Generics,
+};
 
 #[proc_macro_derive(StructConf, attributes(conf))]
 pub fn derive_conf(input: TokenStream) -> TokenStream {
@@ -26,17 +33,24 @@ pub fn derive_conf(input: TokenStream) -> TokenStream {
 
     let result = match ast.data {
         Data::Struct(DataStruct {
-            fields: Fields::Named(named_fields),
+            fields: Fields::Named(ref named_fields),
             ..
-        }) => impl_conf_macro(name, named_fields),
+        }) => StructAttrs::from_derive_input(&ast)
+            .map_err(Error::from)
+            .and_then(|attrs| impl_conf_macro(name, &ast.generics, named_fields.clone(), &attrs)),
         Data::Struct(_) => Err(Error {
             kind: ErrorKind::DeriveType("unnamed struct".to_string()),
             span,
         }),
-        Data::Enum(_) => Err(Error {
-            kind: ErrorKind::DeriveType("enum".to_string()),
-            span,
-        }),
+        Data::Enum(ref data) => {
+            // Reported with the span of each attribute.
+            if let Err(e) = attrs::check_enum_attrs(&ast.attrs) {
+                return e.write_errors().into();
+            }
+            StructAttrs::from_derive_input(&ast)
+                .map_err(Error::from)
+                .and_then(|attrs| impl_enum_macro(name, &ast.generics, data, &attrs))
+        }
         Data::Union(_) => Err(Error {
             kind: ErrorKind::DeriveType("union".to_string()),
             span,
@@ -49,138 +63,776 @@ pub fn derive_conf(input: TokenStream) -> TokenStream {
     }
 }
 
-fn impl_conf_macro(name: &Ident, fields: FieldsNamed) -> Result<TokenStream> {
+fn impl_conf_macro(
+    name: &Ident,
+    generics: &Generics,
+    fields: FieldsNamed,
+    attrs: &StructAttrs,
+) -> Result<TokenStream> {
+    attrs.check_values()?;
+
+    let original_generics = generics;
+    let mut generics = generics.clone();
+    let bounds = &mut generics.make_where_clause().predicates;
+
     let mut options = Vec::new();
+    let mut fields_base = Vec::new();
     let mut tok_fields = Vec::new();
+    let mut tok_names = Vec::new();
+    let mut tok_required = Vec::new();
+    let mut tok_options = Vec::new();
+    let mut tok_owned_options = Vec::new();
     // Iterating the fields from the struct and obtaining the options they
     // are made up of. A field can represent a config file option, an
-    // argument, both, or none. With all these options combined, the code
-    // required for the trait implementation is generated.
+    // argument, an environment variable, any combination of them, or none.
+    // With all these options combined, the code required for the trait
+    // implementation is generated.
     for field in fields.named.into_iter() {
-        let attr = Attrs::init(field)?;
-        let (opt1, opt2) = attr.parse_opt()?;
-
-        // If both attributes were returned, `opt1` will be the arguments
-        // and `opt2` will be the config file. Otherwise, `opt1` can be
-        // any kind of option, and `opt2` will be `None`.
-        let name = &opt1.base.id;
-        let default = &opt1.gen_default()?;
-        // The code generated by each option is wrapped by an `if`. Thus,
-        // the config file option requires an `else` when the field is
-        // composed of two options.
-        let first = &opt1.gen_field_init()?;
-        let second = if let Some(opt) = &opt2 {
-            let tok = opt.gen_field_init()?;
-            quote! { else #tok }
+        let attr = Attrs::init(field, attrs)?;
+        let opts = attr.parse_opt()?;
+
+        // The options are returned in order of priority, and they all share
+        // the same base data.
+        let name = &opts[0].base.id;
+        let local = format_ident!("__{}", name);
+        tok_names.push(quote! { #name: #local });
+        tok_options.push(gen_option_info(&opts)?);
+        tok_owned_options.push(gen_owned_options(&opts)?);
+        bounds.extend(gen_bounds(&opts));
+        fields_base.push(Rc::clone(&opts[0].base));
+
+        // Nested structs and subcommands are initialized with a single
+        // expression instead.
+        if let OptKind::Flatten | OptKind::Nested(_) | OptKind::Subcommand = opts[0].kind {
+            let init = opts[0].gen_field_init()?;
+            tok_fields.push(quote! { let #local = #init; });
+            options.extend(opts);
+            continue;
+        }
+
+        let default = &opts[0].gen_default()?;
+        let default_source = quote! {
+            sources.set(ctx.id(stringify!(#name)), ::structconf::Source::Default);
+        };
+        // Required fields fail instead of falling back to their default
+        // value.
+        let fallback = if opts[0].base.required {
+            let missing = gen_missing(&opts);
+            quote! { return ::std::result::Result::Err(#missing); }
         } else {
-            quote! {}
+            quote! {
+                #default_source
+                #default
+            }
         };
 
-        // Priorities: "arguments > config file > defaults". Some of them
-        // may not exist, but the order will be the same.
-        tok_fields.push(quote! {
-            #name: {
-                #first
-                #second
-                else { #default }
+        // Every field is initialized in a closure so that its errors can be
+        // collected instead of returned, in which case it takes its default
+        // value. Required fields have none, so the struct can't be
+        // initialized, which is only reported after checking the rest of
+        // fields.
+        let check = if opts[0].base.required {
+            tok_required.push(quote! {
+                let #local = match #local {
+                    ::std::option::Option::Some(val) => val,
+                    ::std::option::Option::None => {
+                        return ::std::result::Result::Err(errors.incomplete());
+                    }
+                };
+            });
+            quote! { errors.check_required(result)? }
+        } else {
+            quote! { errors.check(result, || #default)? }
+        };
+        let wrap = |init| {
+            quote! {
+                let #local = {
+                    let result = (|| -> ::std::result::Result<_, ::structconf::Error> {
+                        ::std::result::Result::Ok(#init)
+                    })();
+                    #check
+                };
             }
-        });
+        };
+
+        // Maps merge the entries in all their options, so they're
+        // initialized in reverse order of priority.
+        if let OptKind::Empty = opts[0].kind {
+            if opts[0].base.map_key.is_some() {
+                tok_fields.push(quote! {
+                    let #local = {
+                        #default_source
+                        #default
+                    };
+                });
+                options.extend(opts);
+                continue;
+            }
+        }
+        if opts[0].base.map_key.is_some() {
+            let mut init = Vec::new();
+            for opt in opts.iter().rev() {
+                init.push(opt.gen_field_init()?);
+            }
+            tok_fields.push(wrap(quote! {{
+                let mut found = false;
+                let mut entries = ::std::vec::Vec::new();
+                #(#init)*
+                if found {
+                    ::std::iter::FromIterator::from_iter(entries)
+                } else {
+                    #fallback
+                }
+            }}));
+            options.extend(opts);
+            continue;
+        }
 
-        options.push(opt1);
-        if let Some(opt) = opt2 {
-            options.push(opt);
+        // The code generated by each option is wrapped by an `if`. Thus,
+        // every option after the first one requires an `else`.
+        let mut init = Vec::new();
+        for opt in &opts {
+            init.push(opt.gen_field_init()?);
         }
+
+        // Priorities: "arguments > environment > config file > defaults".
+        // Some of them may not exist, but the order will be the same.
+        tok_fields.push(wrap(quote! {
+            #(#init)else*
+            else {
+                #fallback
+            }
+        }));
+
+        options.extend(opts);
     }
 
     check_conflicts(&options)?;
 
     let mut tok_args = Vec::new();
+    let mut tok_subcommands = Vec::new();
     let mut tok_write_file = Vec::new();
     for opt in options {
         if let Some(tok) = opt.gen_arg_init() {
             tok_args.push(tok);
         }
+        if let Some(tok) = opt.gen_subcommands() {
+            tok_subcommands.push(tok);
+        }
         if let Some(tok) = opt.gen_write_file() {
             tok_write_file.push(tok);
         }
     }
 
+    let hooks = quote! {
+        fn __args<'__a>(
+            ctx: &::structconf::Context
+        ) -> ::std::vec::Vec<::structconf::clap::Arg<'__a>> {
+            let mut args = ::std::vec::Vec::new();
+            #(#tok_args)*
+            args
+        }
+
+        fn __subcommands<'__a>(
+            app: ::structconf::clap::App<'__a>,
+            ctx: &::structconf::Context
+        ) -> ::structconf::clap::App<'__a> {
+            #(#tok_subcommands)*
+            app
+        }
+
+        fn __init(
+            args: &::structconf::clap::ArgMatches,
+            file: &::structconf::ini::Ini,
+            ctx: &::structconf::Context,
+            sources: &mut ::structconf::Sources,
+            errors: &mut ::structconf::Errors
+        ) -> ::std::result::Result<Self, ::structconf::Error> {
+            #(#tok_fields)*
+            #(#tok_required)*
+            ::std::result::Result::Ok(Self {
+                #(#tok_names,)*
+            })
+        }
+
+        fn __options(
+            ctx: &::structconf::Context
+        ) -> ::std::vec::Vec<::structconf::meta::OptionInfo> {
+            let mut options = ::std::vec::Vec::new();
+            #(#tok_options)*
+            options
+        }
+
+        fn __owned_options(
+            &self,
+            ctx: &::structconf::Context
+        ) -> ::std::vec::Vec<::structconf::meta::OptionInfo> {
+            let mut options = ::std::vec::Vec::new();
+            #(#tok_owned_options)*
+            options
+        }
+
+        fn __write(
+            &self,
+            conf: &mut ::structconf::ini::Ini,
+            ctx: &::structconf::Context
+        ) {
+            #(#tok_write_file)*
+        }
+    };
+
+    let mut tokens = gen_trait_impl(name, &generics, attrs, hooks)?;
+    if fields_base.iter().any(|base| base.secret) {
+        tokens.extend(gen_debug_impl(name, original_generics, &fields_base));
+    }
+
+    Ok(tokens)
+}
+
+/// Generates the implementation of `StructConf` for an enum, whose variants
+/// are subcommands. Each of them contains a StructConf-derived struct with
+/// the subcommand's options, which are in their own section in the config
+/// file.
+fn impl_enum_macro(
+    name: &Ident,
+    generics: &Generics,
+    data: &DataEnum,
+    attrs: &StructAttrs,
+) -> Result<TokenStream> {
+    attrs.check_values()?;
+
+    let mut generics = generics.clone();
+    let bounds = &mut generics.make_where_clause().predicates;
+
+    let mut names = HashSet::new();
+    let mut tok_subcommands = Vec::new();
+    let mut tok_variants = Vec::new();
+    let mut tok_options = Vec::new();
+    let mut tok_owned_options = Vec::new();
+    let mut tok_write_file = Vec::new();
+    for variant in &data.variants {
+        let attr = VariantAttrs::init(variant)?;
+        let ident = &attr.ident;
+        let ty = attr.ty.as_ref().unwrap();
+        let sub = attr.name();
+        let section = attr.section();
+        if !names.insert(sub.clone()) {
+            return Err(Error {
+                kind: ErrorKind::ConflictIDs("name".to_string(), sub),
+                span: ident.span(),
+            });
+        }
+
+        bounds.push(parse_quote_spanned! { ty.span()=>
+            #ty: ::structconf::StructConf
+        });
+
+        let ctx = quote! { &ctx.subcommand(#sub, #section) };
+        let about = attr.help.as_ref().map(|help| quote! { .about(#help) });
+        tok_subcommands.push(quote! {
+            let app = app.subcommand({
+                let sub = ::structconf::clap::App::new(#sub) #about;
+                let sub = sub.args(<#ty as ::structconf::StructConf>::__args(#ctx));
+                <#ty as ::structconf::StructConf>::__subcommands(sub, #ctx)
+            });
+        });
+        tok_variants.push(quote! {
+            ::std::option::Option::Some((#sub, args)) => ::std::result::Result::Ok(
+                Self::#ident(<#ty as ::structconf::StructConf>::__init(
+                    args, file, #ctx, sources, errors,
+                )?),
+            ),
+        });
+        tok_options.push(quote! {
+            options.extend(<#ty as ::structconf::StructConf>::__options(#ctx));
+        });
+        tok_owned_options.push(quote! {
+            Self::#ident(val) => ::structconf::StructConf::__owned_options(val, #ctx),
+        });
+        tok_write_file.push(quote! {
+            Self::#ident(val) => ::structconf::StructConf::__write(val, conf, #ctx),
+        });
+    }
+
+    let hooks = quote! {
+        fn __args<'__a>(
+            ctx: &::structconf::Context
+        ) -> ::std::vec::Vec<::structconf::clap::Arg<'__a>> {
+            ::std::vec::Vec::new()
+        }
+
+        fn __subcommands<'__a>(
+            app: ::structconf::clap::App<'__a>,
+            ctx: &::structconf::Context
+        ) -> ::structconf::clap::App<'__a> {
+            let app = app.subcommand_required(true);
+            #(#tok_subcommands)*
+            app
+        }
+
+        fn __init(
+            args: &::structconf::clap::ArgMatches,
+            file: &::structconf::ini::Ini,
+            ctx: &::structconf::Context,
+            sources: &mut ::structconf::Sources,
+            errors: &mut ::structconf::Errors
+        ) -> ::std::result::Result<Self, ::structconf::Error> {
+            match args.subcommand() {
+                #(#tok_variants)*
+                _ => ::std::result::Result::Err(::structconf::Error::MissingSubcommand),
+            }
+        }
+
+        fn __options(
+            ctx: &::structconf::Context
+        ) -> ::std::vec::Vec<::structconf::meta::OptionInfo> {
+            let mut options = ::std::vec::Vec::new();
+            #(#tok_options)*
+            options
+        }
+
+        fn __owned_options(
+            &self,
+            ctx: &::structconf::Context
+        ) -> ::std::vec::Vec<::structconf::meta::OptionInfo> {
+            match self {
+                #(#tok_owned_options)*
+            }
+        }
+
+        fn __write(
+            &self,
+            conf: &mut ::structconf::ini::Ini,
+            ctx: &::structconf::Context
+        ) {
+            match self {
+                #(#tok_write_file)*
+            }
+        }
+    };
+
+    gen_trait_impl(name, &generics, attrs, hooks)
+}
+
+/// Generates the implementation of `StructConf` with the hidden methods in
+/// `hooks`, which are specific to structs or enums. The rest of methods are
+/// built on top of them. The generics include the bounds required by the
+/// fields' types.
+fn gen_trait_impl(
+    name: &Ident,
+    generics: &Generics,
+    attrs: &StructAttrs,
+    hooks: TokenStream2,
+) -> Result<TokenStream> {
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    // The format is obtained from the file's extension at runtime unless
+    // it's specified.
+    let format = match &attrs.format {
+        Some(format) => quote! { ::std::option::Option::Some(#format) },
+        None => quote! { ::std::option::Option::None },
+    };
+
+    // The permissions of the config file, if they're restricted.
+    let file_mode = match attrs.file_mode {
+        Some(mode) => quote! { ::std::option::Option::Some(#mode) },
+        None => quote! { ::std::option::Option::None },
+    };
+
+    // Loading a config file, which is migrated right away if it's
+    // versioned, before the fields are extracted.
+    let load_file = match attrs.version {
+        Some(version) => {
+            let migrate = match &attrs.migrate {
+                Some(migrate) => {
+                    let migrate = syn::parse_str::<syn::Path>(migrate)?;
+                    quote! { ::std::option::Option::Some(#migrate) }
+                }
+                None => quote! { ::std::option::Option::None },
+            };
+            let rewrite = attrs.rewrite_migrated;
+            quote! {
+                ::structconf::format::load(path, #format).and_then(|mut doc| {
+                    ::structconf::migrate::run(&mut doc, path, #format, #version, #migrate, #rewrite)
+                        .map(|_| doc)
+                })
+            }
+        }
+        None => quote! { ::structconf::format::load(path, #format) },
+    };
+
+    // The keys in each config file are checked against the known ones right
+    // after loading it, in strict mode.
+    let strict = attrs.deny_unknown || attrs.warn_unknown;
+    let deny = attrs.deny_unknown;
+    let known_options = match (strict, attrs.version) {
+        (false, _) => quote! {},
+        (true, Some(version)) => quote! {
+            let mut known = Self::options();
+            known.push(::structconf::migrate::option(#version));
+        },
+        (true, None) => quote! { let known = Self::options(); },
+    };
+    let check_unknown = if strict {
+        quote! { ::structconf::strict::check(&layer, path, &known, #deny) }
+    } else {
+        quote! { ::std::result::Result::Ok::<(), ::structconf::Error>(()) }
+    };
+
+    // What to do when the config file doesn't exist, as an expression that
+    // returns `Result<(), Error>`.
+    let create = match attrs.version {
+        Some(version) => quote! {
+            ::structconf::migrate::create(path, #format, #file_mode, #version)
+        },
+        None => quote! {
+            ::structconf::write::create(path, #file_mode).map_err(::structconf::Error::from)
+        },
+    };
+    let missing_file = match attrs.missing_file.as_deref() {
+        Some("empty") => quote! { ::std::result::Result::Ok::<(), ::structconf::Error>(()) },
+        Some("error") => quote! {
+            ::std::result::Result::Err(::structconf::Error::NotFound(path.to_string()))
+        },
+        // Templates are only available for INI files, so the rest are
+        // created empty.
+        Some("template") => quote! {
+            if ::structconf::format::is_ini(::std::path::Path::new(path), #format) {
+                Self::write_template(path)
+            } else {
+                ::structconf::diagnostics::emit(::structconf::Diagnostic::NoTemplate {
+                    path: path.to_string(),
+                });
+                #create
+            }
+            .map(|_| {
+                ::structconf::diagnostics::emit(::structconf::Diagnostic::CreatedFile {
+                    path: path.to_string(),
+                });
+            })
+        },
+        _ => quote! {
+            #create.map(|_| {
+                ::structconf::diagnostics::emit(::structconf::Diagnostic::CreatedFile {
+                    path: path.to_string(),
+                });
+            })
+        },
+    };
+    // The version is written into the config file like another option, and
+    // at the top of the templates.
+    let (write_template, write_version) = match attrs.version {
+        Some(version) => (
+            quote! {
+                ::structconf::migrate::write_template(
+                    &Self::options(),
+                    path,
+                    #format,
+                    #file_mode,
+                    #version,
+                )
+            },
+            quote! {
+                conf.with_section(::std::option::Option::Some(::structconf::migrate::SECTION))
+                    .set(::structconf::migrate::KEY, #version.to_string());
+                owned.push(::structconf::migrate::option(#version));
+            },
+        ),
+        None => (
+            quote! {
+                ::structconf::meta::write_template(&Self::options(), path, #format, #file_mode)
+            },
+            quote! {},
+        ),
+    };
+    let prepare_file = quote! {
+        if ::std::path::Path::new(path).exists() {
+            ::std::result::Result::Ok(())
+        } else {
+            #missing_file.map_err(::structconf::Error::from)
+        }
+    };
+
     let trait_impl = quote! {
-        impl StructConf for #name {
+        impl #impl_generics StructConf for #name #ty_generics #where_clause {
             fn parse(
                 app: ::structconf::clap::App,
                 path: &str
-            ) -> ::std::result::Result<#name, ::structconf::Error>
+            ) -> ::std::result::Result<Self, ::structconf::Error>
                 where
                     Self: ::std::marker::Sized
             {
-                let args = #name::parse_args(app);
-                #name::parse_file(&args, path)
+                // The conflicts are reported as an error before clap finds
+                // them.
+                let ctx = ::structconf::Context::root();
+                let app = Self::__subcommands(app.args(Self::__args(&ctx)), &ctx);
+                ::structconf::meta::check_args(&app)?;
+                let args = app.get_matches_from(&mut ::std::env::args());
+                Self::parse_file(&args, path)
             }
 
             fn parse_args(
                 app: ::structconf::clap::App
             ) -> ::structconf::clap::ArgMatches {
-                #name::parse_args_from(
+                Self::parse_args_from(
                     app,
                     &mut ::std::env::args()
                 )
             }
 
-            fn parse_args_from<I, T>(
+            fn parse_args_from<__I, __T>(
                 app: ::structconf::clap::App,
-                iter: I,
+                iter: __I,
             ) -> ::structconf::clap::ArgMatches
                 where
-                    I: ::std::iter::IntoIterator<Item = T>,
-                    T: ::std::convert::Into<::std::ffi::OsString>
+                    __I: ::std::iter::IntoIterator<Item = __T>,
+                    __T: ::std::convert::Into<::std::ffi::OsString>
                         + ::std::clone::Clone {
-                app.args(&[
-                    #(#tok_args,)*
-                ]).get_matches_from(iter)
+                let ctx = ::structconf::Context::root();
+                let app = Self::__subcommands(app.args(Self::__args(&ctx)), &ctx);
+                if let ::std::result::Result::Err(e) = ::structconf::meta::check_args(&app) {
+                    ::std::panic!("{}", e);
+                }
+                app.get_matches_from(iter)
+            }
+
+            fn parse_with_sources(
+                app: ::structconf::clap::App,
+                path: &str
+            ) -> ::std::result::Result<(Self, ::structconf::Sources), ::structconf::Error>
+                where
+                    Self: ::std::marker::Sized
+            {
+                // The conflicts are reported as an error before clap finds
+                // them.
+                let ctx = ::structconf::Context::root();
+                let app = Self::__subcommands(app.args(Self::__args(&ctx)), &ctx);
+                ::structconf::meta::check_args(&app)?;
+                let args = app.get_matches_from(&mut ::std::env::args());
+                Self::parse_file_with_sources(&args, path)
             }
 
             fn parse_file(
                 args: &::structconf::clap::ArgMatches,
                 path: &str
-            ) -> ::std::result::Result<#name, ::structconf::Error>
+            ) -> ::std::result::Result<Self, ::structconf::Error>
+                where
+                    Self: ::std::marker::Sized {
+                Self::parse_file_with_sources(args, path).map(|(conf, _)| conf)
+            }
+
+            fn parse_file_with_sources(
+                args: &::structconf::clap::ArgMatches,
+                path: &str
+            ) -> ::std::result::Result<(Self, ::structconf::Sources), ::structconf::Error>
                 where
                     Self: ::std::marker::Sized {
                 // Checking that the config file exists, and creating it
-                // otherwise.
-                let path_wrap = ::std::path::Path::new(path);
-                if !path_wrap.exists() {
-                    ::std::fs::File::create(&path_wrap)?;
-                    eprintln!("Created config file at {}", path);
+                // otherwise, unless configured differently.
+                #prepare_file?;
+
+                Self::parse_file_layers_with_sources(args, &[path])
+            }
+
+            fn parse_layers(
+                app: ::structconf::clap::App,
+                paths: &[&str]
+            ) -> ::std::result::Result<Self, ::structconf::Error>
+                where
+                    Self: ::std::marker::Sized
+            {
+                // The conflicts are reported as an error before clap finds
+                // them.
+                let ctx = ::structconf::Context::root();
+                let app = Self::__subcommands(app.args(Self::__args(&ctx)), &ctx);
+                ::structconf::meta::check_args(&app)?;
+                let args = app.get_matches_from(&mut ::std::env::args());
+                Self::parse_file_layers(&args, paths)
+            }
+
+            fn parse_file_layers(
+                args: &::structconf::clap::ArgMatches,
+                paths: &[&str]
+            ) -> ::std::result::Result<Self, ::structconf::Error>
+                where
+                    Self: ::std::marker::Sized {
+                Self::parse_file_layers_with_sources(args, paths).map(|(conf, _)| conf)
+            }
+
+            fn parse_file_layers_with_sources(
+                args: &::structconf::clap::ArgMatches,
+                paths: &[&str]
+            ) -> ::std::result::Result<(Self, ::structconf::Sources), ::structconf::Error>
+                where
+                    Self: ::std::marker::Sized {
+                // The layers are merged in order, and the missing ones are
+                // skipped.
+                let options = Self::options();
+                ::structconf::meta::check_keys(&options)?;
+                ::structconf::meta::check_envs(&options)?;
+
+                let mut file = ::structconf::ini::Ini::new();
+                let mut sources = ::structconf::Sources::new();
+                #known_options
+                for path in paths {
+                    if !::std::path::Path::new(path).exists() {
+                        continue;
+                    }
+
+                    let layer = #load_file?;
+                    #check_unknown?;
+                    sources.set_origins(path, &layer);
+                    ::structconf::format::merge(&mut file, &layer);
                 }
 
-                let file = ::structconf::ini::Ini::load_from_file(path)?;
-                Ok(#name {
-                    #(#tok_fields,)*
-                })
+                let conf = Self::__init(
+                    args,
+                    &file,
+                    &::structconf::Context::root(),
+                    &mut sources,
+                    &mut ::structconf::Errors::new(false),
+                )
+                .map_err(::structconf::Error::locate)?;
+                ::std::result::Result::Ok((conf, sources))
+            }
+
+            fn parse_file_all_errors(
+                args: &::structconf::clap::ArgMatches,
+                path: &str
+            ) -> (::std::option::Option<Self>, ::std::vec::Vec<::structconf::Error>)
+                where
+                    Self: ::std::marker::Sized {
+                let mut errors = ::structconf::Errors::new(true);
+                let options = Self::options();
+                if let ::std::result::Result::Err(e) =
+                    ::structconf::meta::check_keys(&options)
+                        .and_then(|_| ::structconf::meta::check_envs(&options))
+                {
+                    errors.push(e);
+                }
+
+                // Same as `parse_file`, but if the config file can't be
+                // read, the rest of the sources are still used.
+                let mut file = ::structconf::ini::Ini::new();
+                let mut sources = ::structconf::Sources::new();
+                let loaded = #prepare_file.and_then(|_| {
+                    if ::std::path::Path::new(path).exists() {
+                        #load_file
+                    } else {
+                        ::std::result::Result::Ok(::structconf::ini::Ini::new())
+                    }
+                });
+                #known_options
+                match loaded {
+                    ::std::result::Result::Ok(layer) => {
+                        if let ::std::result::Result::Err(e) = #check_unknown {
+                            errors.push(e);
+                        }
+                        sources.set_origins(path, &layer);
+                        file = layer;
+                    }
+                    ::std::result::Result::Err(e) => errors.push(e),
+                }
+
+                let conf = Self::__init(
+                    args,
+                    &file,
+                    &::structconf::Context::root(),
+                    &mut sources,
+                    &mut errors,
+                );
+                errors.finish(conf)
+            }
+
+            fn options() -> ::std::vec::Vec<::structconf::meta::OptionInfo> {
+                Self::__options(&::structconf::Context::root())
+            }
+
+            fn write_template(
+                path: &str
+            ) -> ::std::result::Result<(), ::structconf::Error> {
+                #write_template
             }
 
             fn write_file(
                 &self,
                 path: &str
             ) -> ::std::result::Result<(), ::structconf::Error> {
-                let mut conf = ::structconf::ini::Ini::new();
-                #(#tok_write_file)*
-                conf.write_to_file(path)?;
+                self.write_file_with(path, &::structconf::WriteOptions::new())
+            }
 
-                Ok(())
+            fn write_file_with(
+                &self,
+                path: &str,
+                options: &::structconf::WriteOptions,
+            ) -> ::std::result::Result<(), ::structconf::Error> {
+                let mut conf = ::structconf::ini::Ini::new();
+                self.__write(&mut conf, &::structconf::Context::root());
+                let mut owned = self.__owned_options(&::structconf::Context::root());
+                #write_version
+                ::structconf::write::save(
+                    &conf,
+                    &owned,
+                    path,
+                    #format,
+                    #file_mode,
+                    options,
+                )
             }
+
+            #hooks
         }
     };
 
     Ok(trait_impl.into())
 }
 
+/// Generates the implementation of `Debug` for structs with secrets, which
+/// are redacted. Thus, it can't be derived as well.
+fn gen_debug_impl(name: &Ident, generics: &Generics, fields: &[Rc<OptBaseData>]) -> TokenStream {
+    let mut generics = generics.clone();
+    let bounds = &mut generics.make_where_clause().predicates;
+    let mut tok_fields = Vec::new();
+    for base in fields {
+        let id = &base.id;
+        let val = match (base.secret, base.is_option) {
+            (true, true) => quote! {
+                &self.#id.as_ref().map(|_| ::structconf::secret::Redacted)
+            },
+            (true, false) => quote! { &::structconf::secret::Redacted },
+            (false, _) => {
+                let ty = &base.field_ty;
+                bounds.push(parse_quote_spanned! { ty.span()=>
+                    #ty: ::std::fmt::Debug
+                });
+                quote! { &self.#id }
+            }
+        };
+        tok_fields.push(quote! { .field(stringify!(#id), #val) });
+    }
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let debug_impl = quote! {
+        impl #impl_generics ::std::fmt::Debug for #name #ty_generics #where_clause {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                f.debug_struct(stringify!(#name))
+                    #(#tok_fields)*
+                    .finish()
+            }
+        }
+    };
+
+    debug_impl.into()
+}
+
 // Looks for conflicts in the options as a whole, like repeated IDs.
 fn check_conflicts(opts: &[Opt]) -> Result<()> {
     let mut files = HashSet::new();
+    let mut sections = HashSet::<String>::new();
+    let mut envs = HashSet::<String>::new();
     let mut longs = HashSet::<String>::new();
     let mut shorts = HashSet::<char>::new();
+    let mut subcommands = HashSet::<bool>::new();
 
     macro_rules! try_insert {
         ($iter:expr, $new:expr, $span:expr, $err_id:expr) => {
@@ -197,18 +849,51 @@ fn check_conflicts(opts: &[Opt]) -> Result<()> {
 
     for opt in opts {
         let span = opt.base.id.span();
+        // The aliases are reported as such when they collide.
+        let attr = |name| if opt.is_alias() { "alias" } else { name };
         match &opt.kind {
-            OptKind::Empty => {}
+            OptKind::Empty | OptKind::Flatten | OptKind::Nested(_) => {}
+            // A struct may only have a single subcommand.
+            OptKind::Subcommand => {
+                try_insert!(subcommands, true, span, "subcommand");
+            }
             OptKind::Flag(arg) | OptKind::Arg(arg) => {
                 if let Some(short) = arg.short {
                     try_insert!(shorts, short, span, "short");
                 }
                 if let Some(long) = &arg.long {
-                    try_insert!(longs, long.clone(), span, "long");
+                    try_insert!(longs, long.clone(), span, attr("long"));
+                    if opt.base.secret {
+                        try_insert!(longs, format!("{}-file", long), span, attr("long"));
+                    }
+                }
+            }
+            OptKind::Env(env) => {
+                try_insert!(envs, env.name.clone(), span, "env");
+                if opt.base.secret {
+                    try_insert!(envs, format!("{}_FILE", env.name), span, "env");
                 }
             }
             OptKind::File(file) => {
-                try_insert!(files, file.name.clone(), span, "file");
+                try_insert!(files, file.name.clone(), span, attr("file"));
+                if opt.base.secret {
+                    try_insert!(files, format!("{}_file", file.name), span, attr("file"));
+                }
+            }
+            OptKind::Section(section) => {
+                try_insert!(sections, section.section.clone(), span, "section_map");
+            }
+        }
+    }
+
+    // The sections taken by maps can't be used by other options.
+    for opt in opts {
+        if let OptKind::File(file) = &opt.kind {
+            if sections.contains(&file.section) {
+                return Err(Error {
+                    kind: ErrorKind::ConflictIDs("section".to_string(), file.section.clone()),
+                    span: opt.base.id.span(),
+                });
             }
         }
     }
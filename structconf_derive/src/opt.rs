@@ -11,25 +11,89 @@ use crate::error::Result;
 use proc_macro2::TokenStream as TokenStream2;
 use quote::quote;
 use std::rc::Rc;
-use syn::{Expr, Ident, Type};
+use syn::spanned::Spanned;
+use syn::{parse_quote_spanned, Expr, Ident, Path, Type, WherePredicate};
 
 pub struct OptBaseData {
     pub id: Ident,
     pub ty: Type,
+    pub field_ty: Type,
+    pub help: Option<String>,
     pub is_option: bool,
+    pub is_vec: bool,
+    /// The type of the keys, in case it's a map. `ty` is the type of the
+    /// values.
+    pub map_key: Option<Type>,
+    pub delimiter: Option<char>,
+    /// Paths don't implement `Display`, so they're converted to strings
+    /// differently, and they're completed as files in the shell.
+    pub is_path: bool,
+    /// Secrets are redacted, skipped when writing, and may also be read
+    /// from a file.
+    pub secret: bool,
+    /// The former names of the long argument and the key in the config
+    /// file, which are still accepted.
+    pub aliases: Vec<String>,
+    /// The note shown when the aliases are used, or when the option itself
+    /// is used if it has none.
+    pub deprecated: Option<String>,
+    /// Required options fail instead of taking their default value.
+    pub required: bool,
+    pub validation: OptValidationData,
     pub default: Option<String>,
 }
 
+impl OptBaseData {
+    /// Generates the conversion of a value to the string that's written in
+    /// the config file.
+    pub fn gen_to_string(&self, val: TokenStream2) -> TokenStream2 {
+        if self.is_path {
+            quote! { #val.display().to_string() }
+        } else {
+            quote! { #val.to_string() }
+        }
+    }
+}
+
+/// The checks a value must pass after being parsed, whichever source it
+/// came from.
+pub struct OptValidationData {
+    pub range: Option<Expr>,
+    pub regex: Option<String>,
+    pub non_empty: bool,
+    pub validate: Option<Path>,
+}
+
 pub struct OptArgData {
     pub long: Option<String>,
     pub short: Option<char>,
     pub help: Option<String>,
     pub negated: bool,
+    /// The values the argument may take, which are also used for the shell
+    /// completions.
+    pub possible_values: Option<Expr>,
+    /// Whether it's a hidden argument for one of the field's aliases.
+    pub alias: bool,
+}
+
+pub struct OptEnvData {
+    pub name: String,
 }
 
 pub struct OptFileData {
     pub name: String,
     pub section: String,
+    /// Whether it's the key of one of the field's aliases.
+    pub alias: bool,
+}
+
+pub struct OptSectionData {
+    pub section: String,
+}
+
+pub struct OptNestedData {
+    pub prefix: String,
+    pub section: String,
 }
 
 pub enum OptKind {
@@ -39,8 +103,19 @@ pub enum OptKind {
     Arg(OptArgData),
     /// An argument that doesn't take value
     Flag(OptArgData),
+    /// An environment variable
+    Env(OptEnvData),
     /// A config file option
     File(OptFileData),
+    /// A config file section whose keys are all part of the option
+    Section(OptSectionData),
+    /// A struct whose options are merged into the parent's
+    Flatten,
+    /// A struct whose options are in their own section, and whose arguments
+    /// are prefixed
+    Nested(OptNestedData),
+    /// An enum whose variants are subcommands
+    Subcommand,
 }
 
 pub struct Opt {
@@ -53,6 +128,33 @@ pub struct Opt {
 }
 
 impl Opt {
+    /// Whether the option is one of the field's aliases.
+    pub fn is_alias(&self) -> bool {
+        match &self.kind {
+            OptKind::Arg(arg) | OptKind::Flag(arg) => arg.alias,
+            OptKind::File(file) => file.alias,
+            _ => false,
+        }
+    }
+
+    /// The identifier of the argument, which is the field's, or the field's
+    /// followed by the alias for its hidden arguments.
+    fn arg_id(&self) -> String {
+        match &self.kind {
+            OptKind::Arg(OptArgData {
+                long: Some(long),
+                alias: true,
+                ..
+            })
+            | OptKind::Flag(OptArgData {
+                long: Some(long),
+                alias: true,
+                ..
+            }) => format!("{}-{}", self.base.id, long),
+            _ => self.base.id.to_string(),
+        }
+    }
+
     /// Generates the default value the option will take as a fallback.
     pub fn gen_default(&self) -> Result<TokenStream2> {
         match &self.base.default {
@@ -79,31 +181,53 @@ impl Opt {
         }
     }
 
+    /// Generates the context nested structs are initialized with.
+    fn gen_nested_ctx(&self) -> Option<TokenStream2> {
+        match &self.kind {
+            // Each subcommand has its own context, obtained by the enum.
+            OptKind::Flatten | OptKind::Subcommand => Some(quote! { ctx }),
+            OptKind::Nested(OptNestedData { prefix, section }) => {
+                let id = self.base.id.to_string();
+                Some(quote! {
+                    &ctx.nested(#id, #prefix, #section)
+                })
+            }
+            _ => None,
+        }
+    }
+
     /// Generates the field initialization logic. This may read data from the
     /// config file or the argument parser results following the structure
     /// found in the main file's `impl_conf_macro`, which combines all the
     /// options for a field in order.
+    ///
+    /// Nested structs are the exception, since they only consist of a single
+    /// expression that initializes the entire struct.
     pub fn gen_field_init(&self) -> Result<TokenStream2> {
-        let name = &self.base.id;
         let ty = &self.base.ty;
-        let parse = quote! {
-            let val = val
-                .parse::<#ty>()
-                .map_err(|e| {
-                    ::structconf::Error::Parse(e.to_string())
-                })?;
-        };
+        let parse = self.gen_parse(&self.gen_source_expr());
+        let source = self.gen_source();
         let ret = if self.base.is_option {
             quote! { ::std::option::Option::Some(val) }
         } else {
             quote! { val }
         };
+        let secret = self.gen_secret_init(&ret);
+        let arg_id = self.arg_id();
+
+        if self.base.is_vec {
+            return self.gen_list_init(parse);
+        }
+        if self.base.map_key.is_some() {
+            return Ok(self.gen_map_init());
+        }
 
         match &self.kind {
             OptKind::Empty => {
                 let default = self.gen_default()?;
                 Ok(quote! {
                     if true {
+                        #source
                         #default
                     }
                 })
@@ -116,96 +240,705 @@ impl Opt {
                 };
 
                 Ok(quote! {
-                    if args.is_present(stringify!(#name)) {
+                    if args.is_present(&*ctx.id(#arg_id)) {
+                        #source
                         #ret
                     }
                 })
             }
             OptKind::Arg(_) => Ok(quote! {
                 if let ::std::option::Option::Some(val)
-                        = args.value_of(stringify!(#name)) {
+                        = args.value_of(&*ctx.id(#arg_id)) {
                     #parse
+                    #source
                     #ret
-                }
+                } #secret
+            }),
+            OptKind::Env(OptEnvData { name }) => Ok(quote! {
+                if let ::std::result::Result::Ok(val) = ::std::env::var(#name) {
+                    #parse
+                    #source
+                    #ret
+                } #secret
             }),
-            OptKind::File(OptFileData { name, section }) => Ok(quote! {
+            OptKind::File(OptFileData { name, section, .. }) => Ok(quote! {
                 if let ::std::option::Option::Some(val) = file.get_from(
-                    ::std::option::Option::Some(#section),
+                    ::std::option::Option::Some(ctx.section(#section)),
                     #name,
                 ) {
                     #parse
+                    #source
                     #ret
-                }
+                } #secret
             }),
+            OptKind::Flatten | OptKind::Nested(_) => {
+                let ctx = self.gen_nested_ctx();
+                Ok(quote! {
+                    <#ty as ::structconf::StructConf>::__init(args, file, #ctx, sources, errors)?
+                })
+            }
+            // Optional subcommands are `None` if none was used.
+            OptKind::Subcommand => {
+                let init = quote! {
+                    <#ty as ::structconf::StructConf>::__init(args, file, ctx, sources, errors)?
+                };
+                if self.base.is_option {
+                    Ok(quote! {
+                        if args.subcommand().is_some() {
+                            ::std::option::Option::Some(#init)
+                        } else {
+                            ::std::option::Option::None
+                        }
+                    })
+                } else {
+                    Ok(init)
+                }
+            }
+            // Only maps can take a section, and they're handled above.
+            OptKind::Section(_) => unreachable!(),
+        }
+    }
+
+    /// Generates the logic to read a secret from the file whose path is in
+    /// the option's counterpart, like `--token-file`, `TOKEN_FILE` or
+    /// `token_file` for `--token`, `TOKEN` and `token`. It's chained to the
+    /// option's own logic with an `else`, so that the value itself takes
+    /// priority.
+    fn gen_secret_init(&self, ret: &TokenStream2) -> Option<TokenStream2> {
+        if !self.base.secret {
+            return None;
+        }
+
+        let id = &self.base.id;
+        let (lookup, source) = match &self.kind {
+            OptKind::Arg(OptArgData {
+                long: Some(long), ..
+            }) => {
+                let file_id = format!("{}_file", self.arg_id());
+                let long = format!("{}-file", long);
+                (
+                    quote! { ::std::option::Option::Some(path) = args.value_of(&*ctx.id(#file_id)) },
+                    quote! {
+                        ::structconf::Source::Argument {
+                            name: ctx.arg_name(
+                                ::std::option::Option::Some(#long),
+                                ::std::option::Option::None,
+                            ),
+                        }
+                    },
+                )
+            }
+            OptKind::Env(OptEnvData { name }) => {
+                let name = format!("{}_FILE", name);
+                (
+                    quote! { ::std::result::Result::Ok(path) = ::std::env::var(#name) },
+                    quote! {
+                        ::structconf::Source::Env {
+                            name: #name.to_string(),
+                        }
+                    },
+                )
+            }
+            OptKind::File(OptFileData { name, section, .. }) => {
+                let name = format!("{}_file", name);
+                (
+                    quote! {
+                        ::std::option::Option::Some(path) = file.get_from(
+                            ::std::option::Option::Some(ctx.section(#section)),
+                            #name,
+                        )
+                    },
+                    quote! {
+                        sources.file(ctx.section(#section), ::std::option::Option::Some(#name))
+                    },
+                )
+            }
+            _ => return None,
+        };
+        let parse = self.gen_parse(&source);
+        let set_source = self.gen_set_source(source);
+
+        Some(quote! {
+            else if let #lookup {
+                let val = ::structconf::secret::read(&ctx.id(stringify!(#id)), &path)?;
+                #parse
+                #set_source
+                #ret
+            }
+        })
+    }
+
+    /// Generates the expression with the source of the field's value when
+    /// it's obtained from this option.
+    fn gen_source_expr(&self) -> TokenStream2 {
+        match &self.kind {
+            OptKind::Empty | OptKind::Flatten | OptKind::Nested(_) | OptKind::Subcommand => {
+                quote! {
+                    ::structconf::Source::Default
+                }
+            }
+            OptKind::Arg(OptArgData { long, short, .. })
+            | OptKind::Flag(OptArgData { long, short, .. }) => {
+                let long = match long {
+                    Some(long) => quote! { ::std::option::Option::Some(#long) },
+                    None => quote! { ::std::option::Option::None },
+                };
+                let short = match short {
+                    Some(short) => quote! { ::std::option::Option::Some(#short) },
+                    None => quote! { ::std::option::Option::None },
+                };
+                quote! {
+                    ::structconf::Source::Argument {
+                        name: ctx.arg_name(#long, #short),
+                    }
+                }
+            }
+            OptKind::Env(OptEnvData { name }) => quote! {
+                ::structconf::Source::Env {
+                    name: #name.to_string(),
+                }
+            },
+            OptKind::File(OptFileData { name, section, .. }) => quote! {
+                sources.file(ctx.section(#section), ::std::option::Option::Some(#name))
+            },
+            OptKind::Section(OptSectionData { section }) => quote! {
+                sources.file(&ctx.subsection(#section), ::std::option::Option::None)
+            },
+        }
+    }
+
+    /// Generates the logic to record the source of the field's value when
+    /// it's obtained from this option.
+    fn gen_source(&self) -> TokenStream2 {
+        // The fields of nested structs record their own sources.
+        if let OptKind::Flatten | OptKind::Nested(_) | OptKind::Subcommand = self.kind {
+            return quote! {};
+        }
+
+        self.gen_set_source(self.gen_source_expr())
+    }
+
+    /// Generates the logic to record the `source` of the field's value. The
+    /// deprecated options are reported to the diagnostics hook as well.
+    fn gen_set_source(&self, source: TokenStream2) -> TokenStream2 {
+        let id = &self.base.id;
+        // The aliases are always reported, and so is the option itself if
+        // it's deprecated and has no aliases.
+        let deprecated = self.base.deprecated.is_some() && self.base.aliases.is_empty();
+        let notice = if self.is_alias() || deprecated {
+            let note = match &self.base.deprecated {
+                Some(note) => quote! { ::std::option::Option::Some(#note.to_string()) },
+                None => quote! { ::std::option::Option::None },
+            };
+            Some(quote! {
+                ::structconf::diagnostics::emit(::structconf::Diagnostic::Deprecated {
+                    field: ctx.id(stringify!(#id)).into_owned(),
+                    source: source.clone(),
+                    note: #note,
+                });
+            })
+        } else {
+            None
+        };
+
+        quote! {
+            let source = #source;
+            #notice
+            sources.set(ctx.id(stringify!(#id)), source);
+        }
+    }
+
+    /// Generates the error returned when the value in `raw` can't be parsed
+    /// into what's `expected`, with the reason in `e`.
+    fn gen_parse_error(&self, source: &TokenStream2, expected: &str) -> TokenStream2 {
+        let id = &self.base.id;
+        let value = self.gen_raw_value();
+        quote! {
+            ::structconf::Error::Parse(::std::boxed::Box::new(::structconf::ParseError {
+                field: ctx.id(stringify!(#id)).into_owned(),
+                source: #source,
+                value: #value,
+                expected: #expected.to_string(),
+                line: ::std::option::Option::None,
+                reason: e.to_string(),
+            }))
+        }
+    }
+
+    /// Generates the raw value shown in the errors, which is redacted for
+    /// secrets.
+    fn gen_raw_value(&self) -> TokenStream2 {
+        if self.base.secret {
+            quote! { ::structconf::secret::REDACTED.to_string() }
+        } else {
+            quote! { raw.to_string() }
+        }
+    }
+
+    /// Generates the logic to parse a single value from the string in `val`,
+    /// which is then validated. The parsed value is left in `val`, and
+    /// `source` is where it was obtained from in case of errors.
+    fn gen_parse(&self, source: &TokenStream2) -> TokenStream2 {
+        let name = &self.base.id;
+        let ty = &self.base.ty;
+        let OptValidationData {
+            range,
+            regex,
+            non_empty,
+            validate,
+        } = &self.base.validation;
+
+        // The checks on the raw value are done before parsing it, so that
+        // their error is more helpful than the parser's.
+        let mut raw_checks = Vec::new();
+        if *non_empty {
+            raw_checks.push(quote! { ::structconf::validation::non_empty(raw) });
+        }
+        if let Some(regex) = regex {
+            // The pattern is only compiled the first time it's used.
+            raw_checks.push(quote! {{
+                static REGEX: ::std::sync::OnceLock<::structconf::validation::Regex> =
+                    ::std::sync::OnceLock::new();
+                ::structconf::validation::regex(&REGEX, #regex, raw)
+            }});
+        }
+        let mut checks = Vec::new();
+        if let Some(range) = range {
+            let desc = quote!(#range).to_string().replace(' ', "");
+            checks.push(quote! {
+                ::structconf::validation::range(&(#range), &val, #desc)
+            });
+        }
+        if let Some(validate) = validate {
+            checks.push(quote! {
+                #validate(&val).map_err(|e| e.to_string())
+            });
+        }
+
+        let value = self.gen_raw_value();
+        let fail = quote! {
+            ::structconf::Error::Validation {
+                field: ctx.id(stringify!(#name)).into_owned(),
+                origin: ::std::boxed::Box::new(#source),
+                value: #value,
+                reason,
+            }
+        };
+        let error = self.gen_parse_error(source, &type_name(ty));
+        quote! {
+            let raw: &str = &val;
+            #(
+                if let ::std::result::Result::Err(reason) = #raw_checks {
+                    return ::std::result::Result::Err(#fail);
+                }
+            )*
+            let val = raw
+                .parse::<#ty>()
+                .map_err(|e| #error)?;
+            #(
+                if let ::std::result::Result::Err(reason) = #checks {
+                    return ::std::result::Result::Err(#fail);
+                }
+            )*
+        }
+    }
+
+    /// Generates the field initialization logic for lists, which are read
+    /// from every occurrence of the argument or the key in the config file.
+    /// Each value may be split with the delimiter, except for environment
+    /// variables, which can't be repeated and are always split.
+    fn gen_list_init(&self, parse: TokenStream2) -> Result<TokenStream2> {
+        let source = self.gen_source();
+        let parse_list = |delimiter: Option<char>| {
+            let split = match delimiter {
+                Some(d) => quote! { ::structconf::format::split_list(val, #d) },
+                None => quote! { ::std::iter::once(val) },
+            };
+            quote! {
+                let mut list = ::std::vec::Vec::new();
+                for val in vals {
+                    for val in #split {
+                        let val: &str = &val;
+                        // Empty values are skipped, so that empty lists
+                        // can be written.
+                        if val.is_empty() {
+                            continue;
+                        }
+                        #parse
+                        list.push(val);
+                    }
+                }
+                list
+            }
+        };
+
+        match &self.kind {
+            OptKind::Arg(_) => {
+                let parse_list = parse_list(self.base.delimiter);
+                let arg_id = self.arg_id();
+                Ok(quote! {
+                    if let ::std::option::Option::Some(vals)
+                            = args.values_of(&*ctx.id(#arg_id)) {
+                        #source
+                        #parse_list
+                    }
+                })
+            }
+            OptKind::Env(OptEnvData { name }) => {
+                let parse_list = parse_list(Some(self.base.delimiter.unwrap_or(',')));
+                Ok(quote! {
+                    if let ::std::result::Result::Ok(val) = ::std::env::var(#name) {
+                        let vals = ::std::iter::once(val.as_str());
+                        #source
+                        #parse_list
+                    }
+                })
+            }
+            OptKind::File(OptFileData { name, section, .. }) => {
+                let parse_list = parse_list(self.base.delimiter);
+                Ok(quote! {
+                    if let ::std::option::Option::Some(props) = file
+                        .section(::std::option::Option::Some(ctx.section(#section)))
+                        .filter(|props| props.contains_key(#name))
+                    {
+                        let vals = props.get_all(#name);
+                        #source
+                        #parse_list
+                    }
+                })
+            }
+            // Lists can only be empty or take value.
+            _ => {
+                let default = self.gen_default()?;
+                Ok(quote! {
+                    if true {
+                        #source
+                        #default
+                    }
+                })
+            }
         }
     }
 
-    /// Generates the argument initialization logic for `clap`. This will
-    /// only work for options that represent an argument.
+    /// Generates the field initialization logic for maps. Unlike the rest of
+    /// options, all of their sources are merged, so this pushes the entries
+    /// into `entries` instead. The options should be initialized in reverse
+    /// order of priority, so that the entries with more priority replace the
+    /// others.
+    fn gen_map_init(&self) -> TokenStream2 {
+        let source = self.gen_source();
+        // The entries in a section are reported with their own key.
+        let entry_source = match &self.kind {
+            OptKind::Section(OptSectionData { section }) => quote! {
+                sources.file(&ctx.subsection(#section), ::std::option::Option::Some(entry))
+            },
+            _ => self.gen_source_expr(),
+        };
+        let key_ty = self.base.map_key.as_ref().unwrap();
+        let key_error = self.gen_parse_error(&entry_source, &type_name(key_ty));
+        let pair_error = self.gen_parse_error(&entry_source, "key=value");
+        let parse = self.gen_parse(&entry_source);
+        let parse_entry = quote! {
+            let entry = key.trim();
+            let raw = entry;
+            let key = raw
+                .parse::<#key_ty>()
+                .map_err(|e| #key_error)?;
+            let val = val.trim();
+            #parse
+            entries.push((key, val));
+        };
+
+        let arg_id = self.arg_id();
+        match &self.kind {
+            OptKind::Arg(_) => quote! {
+                if let ::std::option::Option::Some(vals)
+                        = args.values_of(&*ctx.id(#arg_id)) {
+                    found = true;
+                    #source
+                    for val in vals {
+                        let (key, val) = val.split_once('=').ok_or_else(|| {
+                            let raw = val;
+                            let e = "missing `=`";
+                            #pair_error
+                        })?;
+                        #parse_entry
+                    }
+                }
+            },
+            OptKind::Section(OptSectionData { section }) => quote! {
+                if let ::std::option::Option::Some(props) = file.section(
+                    ::std::option::Option::Some(&*ctx.subsection(#section))
+                ) {
+                    found = true;
+                    #source
+                    for (key, val) in props.iter() {
+                        #parse_entry
+                    }
+                }
+            },
+            _ => quote! {},
+        }
+    }
+
+    /// Generates the argument initialization logic for `clap`, which pushes
+    /// the arguments into `args`. This will only work for options that
+    /// represent an argument, or for nested structs.
     pub fn gen_arg_init(&self) -> Option<TokenStream2> {
         match &self.kind {
             OptKind::Arg(OptArgData {
-                help, long, short, ..
+                help,
+                long,
+                short,
+                possible_values,
+                ..
             })
             | OptKind::Flag(OptArgData {
-                help, long, short, ..
+                help,
+                long,
+                short,
+                possible_values,
+                ..
             }) => {
-                let id = self.base.id.to_string();
+                let id = self.arg_id();
                 let mut init = quote! {
-                    ::structconf::clap::Arg::with_name(#id)
+                    let arg = ::structconf::clap::Arg::with_name(ctx.arg_id(#id));
                 };
 
                 if let Some(help) = help {
                     init.extend(quote! {
-                        .help(#help)
+                        let arg = arg.help(#help);
                     });
                 }
 
                 if let Some(long) = long {
                     init.extend(quote! {
-                        .long(#long)
+                        let arg = arg.long(ctx.arg_long(#long));
                     });
                 }
 
                 if let Some(short) = short {
                     init.extend(quote! {
-                        .short(#short)
+                        let arg = match ctx.arg_short(#short) {
+                            ::std::option::Option::Some(short) => arg.short(short),
+                            ::std::option::Option::None => arg,
+                        };
                     });
                 }
 
                 if let OptKind::Arg(_) = self.kind {
                     init.extend(quote! {
-                        .takes_value(true)
+                        let arg = arg.takes_value(true);
+                    });
+                }
+
+                if self.base.is_vec || self.base.map_key.is_some() {
+                    init.extend(quote! {
+                        let arg = arg.multiple_occurrences(true);
+                    });
+                }
+
+                // The values with a delimiter are split when initializing
+                // the field rather than by clap, which doesn't support
+                // escaping it. Thus, their possible values are checked
+                // after splitting them.
+                match (possible_values, self.base.delimiter) {
+                    (Some(values), Some(delimiter)) => init.extend(quote! {
+                        let arg = arg.validator(|val: &str| {
+                            let possible = ::std::iter::IntoIterator::into_iter(#values)
+                                .map(|val: &&'static str| *val)
+                                .collect::<::std::vec::Vec<_>>();
+                            for val in ::structconf::format::split_list(val, #delimiter) {
+                                if !val.is_empty() && !possible.contains(&val.as_str()) {
+                                    return ::std::result::Result::Err(::std::format!(
+                                        "`{}` isn't one of {}",
+                                        val,
+                                        possible.join(", ")
+                                    ));
+                                }
+                            }
+                            ::std::result::Result::Ok(())
+                        });
+                    }),
+                    (Some(values), None) => init.extend(quote! {
+                        let arg = arg.possible_values(
+                            ::std::iter::IntoIterator::into_iter(#values)
+                                .map(|val: &&'static str| *val),
+                        );
+                    }),
+                    _ => {}
+                }
+
+                if self.base.is_path {
+                    init.extend(quote! {
+                        let arg = arg.value_hint(::structconf::clap::ValueHint::FilePath);
                     });
                 }
 
-                Some(init)
+                // The aliases are hidden, and they can't be used along with
+                // the argument itself.
+                let alias = self.is_alias();
+                if alias {
+                    let field_id = self.base.id.to_string();
+                    init.extend(quote! {
+                        let arg = arg.hide(true).conflicts_with(ctx.arg_id(#field_id));
+                    });
+                }
+
+                // Secrets may also be read from a file, whose path is passed
+                // in its own argument.
+                if let (true, Some(long)) = (self.base.secret, long) {
+                    let file_id = format!("{}_file", id);
+                    let file_long = format!("{}-file", long);
+                    let file_help = format!("The file to read `--{}` from", long);
+                    init.extend(quote! {
+                        args.push(arg);
+                        let arg = ::structconf::clap::Arg::with_name(ctx.arg_id(#file_id))
+                            .long(ctx.arg_long(#file_long))
+                            .help(#file_help)
+                            .takes_value(true)
+                            .value_name("PATH")
+                            .value_hint(::structconf::clap::ValueHint::FilePath)
+                            .hide(#alias)
+                            .conflicts_with(ctx.arg_id(#id));
+                    });
+                }
+
+                Some(quote! {{
+                    #init
+                    args.push(arg);
+                }})
+            }
+            OptKind::Flatten | OptKind::Nested(_) => {
+                let ty = &self.base.ty;
+                let ctx = self.gen_nested_ctx();
+                Some(quote! {
+                    args.extend(<#ty as ::structconf::StructConf>::__args(#ctx));
+                })
             }
             _ => None,
         }
     }
 
+    /// Generates the code that adds the subcommands to `app`, which may be
+    /// in nested structs as well.
+    pub fn gen_subcommands(&self) -> Option<TokenStream2> {
+        let ty = &self.base.ty;
+        let ctx = self.gen_nested_ctx()?;
+        let mut init = quote! {
+            let app = <#ty as ::structconf::StructConf>::__subcommands(app, #ctx);
+        };
+        if let (OptKind::Subcommand, true) = (&self.kind, self.base.is_option) {
+            init.extend(quote! {
+                let app = app.subcommand_required(false);
+            });
+        }
+
+        Some(init)
+    }
+
     /// Generates the logic to write to a config file with `rust-ini`. This
-    /// will only work for options available in the config file.
+    /// will only work for options available in the config file, or for
+    /// nested structs.
     pub fn gen_write_file(&self) -> Option<TokenStream2> {
         match &self.kind {
-            OptKind::File(OptFileData { name, section }) => {
+            // Only the current name is written.
+            OptKind::File(OptFileData { alias: true, .. }) => None,
+            OptKind::File(OptFileData { name, section, .. }) => {
                 let id = &self.base.id;
-                if self.base.is_option {
+                if self.base.is_vec {
+                    // Lists are written as a single value if they have a
+                    // delimiter, or as repeated keys otherwise. Empty lists
+                    // are written as an empty value so that they aren't
+                    // replaced by the default value.
+                    let write = match self.base.delimiter {
+                        Some(d) => quote! {
+                            conf.with_section(::std::option::Option::Some(section))
+                                .set(#name, ::structconf::format::join_list(&vals, #d));
+                        },
+                        None => quote! {
+                            if vals.is_empty() {
+                                conf.with_section(::std::option::Option::Some(section))
+                                    .set(#name, "");
+                            }
+                            let props = conf
+                                .entry(::std::option::Option::Some(section.to_string()))
+                                .or_insert_with(::std::default::Default::default);
+                            for val in vals {
+                                props.append(#name, val);
+                            }
+                        },
+                    };
+                    let to_string = self.base.gen_to_string(quote! { val });
+                    Some(quote! {{
+                        let section = ctx.section(#section);
+                        let vals = self.#id
+                            .iter()
+                            .map(|val| #to_string)
+                            .collect::<::std::vec::Vec<_>>();
+                        #write
+                    }})
+                } else if self.base.is_option {
+                    let to_string = self.base.gen_to_string(quote! { val });
                     Some(quote! {
                         if let ::std::option::Option::Some(val) = &self.#id {
                             conf.with_section(
-                                    ::std::option::Option::Some(#section),
+                                    ::std::option::Option::Some(ctx.section(#section)),
                                 )
-                                .set(#name, val.to_string());
+                                .set(#name, #to_string);
                         }
                     })
                 } else {
+                    let to_string = self.base.gen_to_string(quote! { self.#id });
                     Some(quote! {
                         conf.with_section(
-                                ::std::option::Option::Some(#section),
+                                ::std::option::Option::Some(ctx.section(#section)),
                             )
-                            .set(#name, self.#id.to_string());
+                            .set(#name, #to_string);
+                    })
+                }
+            }
+            OptKind::Section(OptSectionData { section }) => {
+                // The entries are sorted so that the order is always the
+                // same, even for a `HashMap`. The section is created even
+                // if it's empty, so that the map isn't replaced by its
+                // default value.
+                let id = &self.base.id;
+                let to_string = self.base.gen_to_string(quote! { val });
+                Some(quote! {{
+                    let props = conf
+                        .entry(::std::option::Option::Some(
+                            ctx.subsection(#section).into_owned(),
+                        ))
+                        .or_insert_with(::std::default::Default::default);
+                    let mut entries = self.#id
+                        .iter()
+                        .map(|(key, val)| (key.to_string(), #to_string))
+                        .collect::<::std::vec::Vec<_>>();
+                    entries.sort();
+                    for (key, val) in entries {
+                        props.insert(key, val);
+                    }
+                }})
+            }
+            OptKind::Flatten | OptKind::Nested(_) => {
+                let id = &self.base.id;
+                let ctx = self.gen_nested_ctx();
+                Some(quote! {
+                    ::structconf::StructConf::__write(&self.#id, conf, #ctx);
+                })
+            }
+            OptKind::Subcommand => {
+                let id = &self.base.id;
+                if self.base.is_option {
+                    Some(quote! {
+                        if let ::std::option::Option::Some(val) = &self.#id {
+                            ::structconf::StructConf::__write(val, conf, ctx);
+                        }
+                    })
+                } else {
+                    Some(quote! {
+                        ::structconf::StructConf::__write(&self.#id, conf, ctx);
                     })
                 }
             }
@@ -213,3 +946,318 @@ impl Opt {
         }
     }
 }
+
+/// Generates the information about a field, given the options it's made up
+/// of, which is pushed into `options`. Nested structs push all of their
+/// fields instead.
+pub fn gen_option_info(opts: &[Opt]) -> Result<TokenStream2> {
+    let base = &opts[0].base;
+    if let OptKind::Flatten | OptKind::Nested(_) | OptKind::Subcommand = opts[0].kind {
+        let ty = &base.ty;
+        let ctx = opts[0].gen_nested_ctx();
+        return Ok(quote! {
+            options.extend(<#ty as ::structconf::StructConf>::__options(#ctx));
+        });
+    }
+
+    let id = &base.id;
+    let ty = type_name(&base.ty);
+    let none = quote! { ::std::option::Option::None };
+    let some = |val: TokenStream2| quote! { ::std::option::Option::Some(#val) };
+    let mut kind = if base.is_option {
+        quote! { Optional }
+    } else if base.is_vec {
+        quote! { List }
+    } else if base.map_key.is_some() {
+        quote! { Map }
+    } else {
+        quote! { Single }
+    };
+    let help = match &base.help {
+        Some(help) => some(quote! { #help.to_string() }),
+        None => none.clone(),
+    };
+    let (mut long, mut short) = (none.clone(), none.clone());
+    let (mut env, mut section, mut key) = (none.clone(), none.clone(), none.clone());
+    let mut default = quote! { ::std::vec::Vec::new() };
+    let mut possible_values = quote! { ::std::vec::Vec::new() };
+    let mut range = none.clone();
+    let mut aliases = Vec::new();
+    // The aliases aren't documented, so they're only listed.
+    for opt in opts.iter().filter(|opt| opt.is_alias()) {
+        if let OptKind::File(OptFileData { name, .. }) = &opt.kind {
+            aliases.push(name);
+        }
+    }
+    for opt in opts.iter().filter(|opt| !opt.is_alias()) {
+        match &opt.kind {
+            OptKind::Arg(arg) | OptKind::Flag(arg) => {
+                if let OptKind::Flag(_) = opt.kind {
+                    kind = quote! { Flag };
+                }
+                if let Some(values) = &arg.possible_values {
+                    possible_values = quote! {
+                        ::std::iter::IntoIterator::into_iter(#values)
+                            .map(|val: &&'static str| val.to_string())
+                            .collect()
+                    };
+                }
+                if let Some(val) = &arg.long {
+                    long = some(quote! { ctx.arg_long(#val).to_string() });
+                }
+                if let Some(val) = &arg.short {
+                    short = quote! { ctx.arg_short(#val) };
+                }
+            }
+            OptKind::Env(OptEnvData { name }) => {
+                env = some(quote! { #name.to_string() });
+            }
+            OptKind::File(OptFileData {
+                name, section: sec, ..
+            }) => {
+                section = some(quote! { ctx.section(#sec).to_string() });
+                key = some(quote! { #name.to_string() });
+            }
+            OptKind::Section(OptSectionData { section: sec }) => {
+                section = some(quote! { ctx.subsection(#sec).into_owned() });
+            }
+            _ => {}
+        }
+
+        // The defaults and the range are only written for the options in the
+        // config file, since they require the type to implement `Display`.
+        // Required fields have no default.
+        if let OptKind::File(_) | OptKind::Section(_) = opt.kind {
+            if let Some(expr) = &base.validation.range {
+                let ty = &base.ty;
+                range = some(quote! {
+                    ::structconf::validation::bounds::<#ty, _>(&(#expr))
+                });
+            }
+            if base.required {
+                continue;
+            }
+
+            let field_ty = &base.field_ty;
+            let expr = opts[0].gen_default()?;
+            let to_string = base.gen_to_string(quote! { val });
+            let to_strings = if base.map_key.is_some() {
+                quote! {{
+                    let mut entries = default
+                        .iter()
+                        .map(|(key, val)| ::std::format!("{} = {}", key, #to_string))
+                        .collect::<::std::vec::Vec<_>>();
+                    entries.sort();
+                    entries
+                }}
+            } else if let (true, Some(d)) = (base.is_vec, base.delimiter) {
+                // Lists with a delimiter are written in a single value.
+                quote! {
+                    if default.is_empty() {
+                        ::std::vec::Vec::new()
+                    } else {
+                        let vals = default
+                            .iter()
+                            .map(|val| #to_string)
+                            .collect::<::std::vec::Vec<_>>();
+                        ::std::vec![::structconf::format::join_list(&vals, #d)]
+                    }
+                }
+            } else if base.is_option || base.is_vec {
+                quote! { default.iter().map(|val| #to_string).collect() }
+            } else {
+                let to_string = base.gen_to_string(quote! { default });
+                quote! { ::std::vec![#to_string] }
+            };
+            default = quote! {{
+                let default: #field_ty = #expr;
+                #to_strings
+            }};
+        }
+    }
+
+    let delimiter = match base.delimiter {
+        Some(d) => some(quote! { #d }),
+        None => none.clone(),
+    };
+    let regex = match &base.validation.regex {
+        Some(regex) => some(quote! { #regex.to_string() }),
+        None => none,
+    };
+    let non_empty = base.validation.non_empty;
+    let secret = base.secret;
+
+    Ok(quote! {
+        options.push(::structconf::meta::OptionInfo {
+            field: ctx.id(stringify!(#id)).into_owned(),
+            ty: #ty.to_string(),
+            kind: ::structconf::meta::ValueKind::#kind,
+            help: #help,
+            long: #long,
+            short: #short,
+            env: #env,
+            section: #section,
+            key: #key,
+            default: #default,
+            delimiter: #delimiter,
+            possible_values: #possible_values,
+            validation: ::structconf::meta::Validation {
+                range: #range,
+                regex: #regex,
+                non_empty: #non_empty,
+            },
+            secret: #secret,
+            aliases: ::std::vec![#(#aliases.to_string()),*],
+            subcommands: ctx.subcommands(),
+        });
+    })
+}
+
+/// Generates the error returned when a required field isn't set by any of
+/// its options, listing where it can be set.
+pub fn gen_missing(opts: &[Opt]) -> TokenStream2 {
+    let id = &opts[0].base.id;
+    let secret = opts[0].base.secret;
+    let mut where_to_set = Vec::new();
+    for opt in opts.iter().filter(|opt| !opt.is_alias()) {
+        // Secrets can also be read from a file, whose path is in the
+        // option's counterpart, as in `gen_secret_init`.
+        match &opt.kind {
+            OptKind::Arg(arg) | OptKind::Flag(arg) => match (&arg.long, &arg.short) {
+                (Some(long), _) => {
+                    let mut longs = vec![long.clone()];
+                    if secret {
+                        longs.push(format!("{}-file", long));
+                    }
+                    where_to_set.push(quote! {
+                        #(where_to_set.push(::std::format!("`--{}`", ctx.arg_long(#longs)));)*
+                    });
+                }
+                (None, Some(short)) => where_to_set.push(quote! {
+                    if let ::std::option::Option::Some(short) = ctx.arg_short(#short) {
+                        where_to_set.push(::std::format!("`-{}`", short));
+                    }
+                }),
+                (None, None) => {}
+            },
+            OptKind::Env(OptEnvData { name }) => {
+                let mut names = vec![name.clone()];
+                if secret {
+                    names.push(format!("{}_FILE", name));
+                }
+                where_to_set.push(quote! {
+                    #(where_to_set.push(::std::format!("`{}` in the environment", #names));)*
+                });
+            }
+            OptKind::File(OptFileData { name, section, .. }) => {
+                let mut names = vec![name.clone()];
+                if secret {
+                    names.push(format!("{}_file", name));
+                }
+                where_to_set.push(quote! {
+                    #(where_to_set.push(::std::format!(
+                        "`{}` in section `[{}]` of the config file",
+                        #names,
+                        ctx.section(#section)
+                    ));)*
+                });
+            }
+            OptKind::Section(OptSectionData { section }) => where_to_set.push(quote! {
+                where_to_set.push(::std::format!(
+                    "section `[{}]` of the config file",
+                    ctx.subsection(#section)
+                ));
+            }),
+            _ => {}
+        }
+    }
+
+    quote! {
+        ::structconf::Error::Missing {
+            field: ctx.id(stringify!(#id)).into_owned(),
+            where_to_set: {
+                let mut where_to_set = ::std::vec::Vec::new();
+                #(#where_to_set)*
+                where_to_set
+            },
+        }
+    }
+}
+
+/// Generates the information about a field like `gen_option_info`, but
+/// only with the options in use by the struct's value, which is `self`.
+/// This only makes a difference for subcommands, whose unused variants are
+/// skipped.
+pub fn gen_owned_options(opts: &[Opt]) -> Result<TokenStream2> {
+    let base = &opts[0].base;
+    let id = &base.id;
+    match (&opts[0].kind, base.is_option) {
+        (OptKind::Subcommand, true) => Ok(quote! {
+            if let ::std::option::Option::Some(val) = &self.#id {
+                options.extend(::structconf::StructConf::__owned_options(val, ctx));
+            }
+        }),
+        (OptKind::Flatten | OptKind::Nested(_) | OptKind::Subcommand, _) => {
+            let ctx = opts[0].gen_nested_ctx();
+            Ok(quote! {
+                options.extend(::structconf::StructConf::__owned_options(&self.#id, #ctx));
+            })
+        }
+        _ => gen_option_info(opts),
+    }
+}
+
+/// Generates the bounds a field's types must satisfy, which are added to
+/// the `where` clause of the implementation. This way, generic type
+/// parameters are constrained only as much as needed, and unsatisfied
+/// bounds are reported at the field instead of deep inside the generated
+/// code.
+pub fn gen_bounds(opts: &[Opt]) -> Vec<WherePredicate> {
+    let base = &opts[0].base;
+    let (ty, field_ty) = (&base.ty, &base.field_ty);
+    let mut bounds = Vec::new();
+    if let OptKind::Flatten | OptKind::Nested(_) | OptKind::Subcommand = opts[0].kind {
+        bounds.push(parse_quote_spanned! { ty.span()=>
+            #ty: ::structconf::StructConf
+        });
+        return bounds;
+    }
+
+    // Values are parsed from strings, unless the field is skipped, and
+    // written back when they're in the config file.
+    let mut parsed = vec![ty];
+    parsed.extend(&base.map_key);
+    let written = opts
+        .iter()
+        .any(|opt| matches!(opt.kind, OptKind::File(_) | OptKind::Section(_)));
+    if let OptKind::Empty = opts[0].kind {
+        parsed.clear();
+    }
+    for ty in parsed {
+        bounds.push(parse_quote_spanned! { ty.span()=>
+            #ty: ::std::str::FromStr
+        });
+        bounds.push(parse_quote_spanned! { ty.span()=>
+            <#ty as ::std::str::FromStr>::Err: ::std::fmt::Display
+        });
+        if written && !base.is_path {
+            bounds.push(parse_quote_spanned! { ty.span()=>
+                #ty: ::std::fmt::Display
+            });
+        }
+    }
+
+    // Required fields never fall back to a default value.
+    if base.default.is_none() && !base.is_option && !base.is_vec && !base.required {
+        bounds.push(parse_quote_spanned! { field_ty.span()=>
+            #field_ty: ::std::default::Default
+        });
+    }
+
+    bounds
+}
+
+/// The name of a type as written by the user, which is shown in the errors.
+fn type_name(ty: &Type) -> String {
+    quote!(#ty).to_string().replace(' ', "")
+}
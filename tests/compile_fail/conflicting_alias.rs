@@ -0,0 +1,13 @@
+//! Aliases can't collide with the names of other fields.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+struct Config {
+    #[conf(no_short)]
+    pub timeout: u64,
+    #[conf(no_short, alias = "timeout")]
+    pub timeout_ms: u64,
+}
+
+fn main() {}
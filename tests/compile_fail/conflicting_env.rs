@@ -0,0 +1,11 @@
+//! Conflicting options for the environment variable.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+struct Config {
+    #[conf(no_env, env = "THING")]
+    pub value: bool,
+}
+
+fn main() {}
@@ -0,0 +1,16 @@
+//! Flattened structs can't have their own section.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+struct Inner {
+    pub value: bool,
+}
+
+#[derive(StructConf)]
+struct Config {
+    #[conf(flatten, section = "Inner")]
+    pub inner: Inner,
+}
+
+fn main() {}
@@ -1,11 +1,11 @@
 //! Conflicting options for the help, which should only be available for
-//! arguments.
+//! arguments and config file options.
 
 use structconf::StructConf;
 
 #[derive(StructConf)]
 struct Config {
-    #[conf(no_short, no_long, help = "out of place help message")]
+    #[conf(no_short, no_long, no_file, env = "VALUE", help = "out of place help message")]
     pub value: bool,
 }
 
@@ -0,0 +1,16 @@
+//! Nested structs can't customize their arguments.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+struct Inner {
+    pub value: bool,
+}
+
+#[derive(StructConf)]
+struct Config {
+    #[conf(nested, short = "x")]
+    pub inner: Inner,
+}
+
+fn main() {}
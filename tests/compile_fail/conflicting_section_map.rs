@@ -0,0 +1,14 @@
+//! A map takes the whole section, so other options can't be in it.
+
+use std::collections::HashMap;
+use structconf::StructConf;
+
+#[derive(StructConf)]
+struct Config {
+    #[conf(section_map = "Aliases")]
+    pub aliases: HashMap<String, String>,
+    #[conf(section = "Aliases")]
+    pub value: String,
+}
+
+fn main() {}
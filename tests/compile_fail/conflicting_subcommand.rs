@@ -0,0 +1,21 @@
+//! Subcommands can't customize their options.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+struct Serve {
+    pub port: u16,
+}
+
+#[derive(StructConf)]
+enum Command {
+    Serve(Serve),
+}
+
+#[derive(StructConf)]
+struct Config {
+    #[conf(subcommand, section = "Command")]
+    pub command: Command,
+}
+
+fn main() {}
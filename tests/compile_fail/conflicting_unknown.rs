@@ -0,0 +1,11 @@
+//! The unknown keys can't be both rejected and reported.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+#[conf(deny_unknown, warn_unknown)]
+struct Config {
+    pub value: i32,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+//! Only lists can have a delimiter.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+struct Config {
+    #[conf(delimiter = ",")]
+    pub value: String,
+}
+
+fn main() {}
@@ -0,0 +1,17 @@
+//! The struct attributes aren't available for enums, which are embedded
+//! into a struct as subcommands.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+struct ServeConfig {
+    port: u16,
+}
+
+#[derive(StructConf)]
+#[conf(env_prefix = "APP_", deny_unknown)]
+enum Command {
+    Serve(ServeConfig),
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+//! The file mode must be valid Unix permissions.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+#[conf(file_mode = 0o10000)]
+struct Config {
+    pub value: i32,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+//! Shouldn't compile because the format doesn't exist.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+#[conf(format = "xml")]
+struct Config {
+    pub value: bool,
+}
+
+fn main() {}
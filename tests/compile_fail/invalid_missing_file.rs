@@ -0,0 +1,11 @@
+//! The behaviour for missing files must be a known one.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+#[conf(missing_file = "ignore")]
+struct Config {
+    pub value: i32,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+//! The range must be a valid Rust expression.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+struct Config {
+    #[conf(range = "1..=")]
+    pub value: i32,
+}
+
+fn main() {}
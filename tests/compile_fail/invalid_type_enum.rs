@@ -1,7 +1,7 @@
 use structconf::StructConf;
 
 #[derive(StructConf)]
-struct Enum {
+enum Enum {
     A,
     B,
 }
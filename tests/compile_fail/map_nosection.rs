@@ -0,0 +1,11 @@
+//! Maps need their own section to be available in the config file.
+
+use std::collections::HashMap;
+use structconf::StructConf;
+
+#[derive(StructConf)]
+struct Config {
+    pub aliases: HashMap<String, String>,
+}
+
+fn main() {}
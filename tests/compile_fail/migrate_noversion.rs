@@ -0,0 +1,16 @@
+//! Migrations are only available for versioned config files.
+
+use structconf::ini::Ini;
+use structconf::StructConf;
+
+#[derive(StructConf)]
+#[conf(migrate = "migrate")]
+struct Config {
+    pub value: i32,
+}
+
+fn migrate(_doc: &mut Ini, _from: u32) -> Result<(), String> {
+    Ok(())
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+//! The regex attribute requires the `regex` feature.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+struct Config {
+    #[conf(regex = "[a-z]+")]
+    pub value: String,
+}
+
+fn main() {}
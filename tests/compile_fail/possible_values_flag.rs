@@ -0,0 +1,11 @@
+//! Flags don't take values, so they can't have possible values.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+struct Config {
+    #[conf(possible_values = "&[\"yes\", \"no\"]")]
+    pub verbose: bool,
+}
+
+fn main() {}
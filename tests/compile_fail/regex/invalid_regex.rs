@@ -0,0 +1,11 @@
+//! The regex is checked when the macro is expanded.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+struct Config {
+    #[conf(regex = "[a-z")]
+    pub value: String,
+}
+
+fn main() {}
@@ -0,0 +1,14 @@
+//! Shouldn't compile because an environment variable is repeated, even
+//! though one of them was obtained from the prefix.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+#[conf(env_prefix = "APP_")]
+struct Config {
+    pub timeout: i32,
+    #[conf(env = "APP_TIMEOUT")]
+    pub other_timeout: i32,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+//! Required fields can't fall back to a default value.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+struct Config {
+    #[conf(required, default = "\"key\".to_string()")]
+    pub api_key: String,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+//! Secrets are read as a whole from their files, so they can't be lists.
+
+use structconf::StructConf;
+
+#[derive(StructConf)]
+struct Config {
+    #[conf(secret)]
+    pub tokens: Vec<String>,
+}
+
+fn main() {}
@@ -0,0 +1,36 @@
+//! Generic structs and enums, whose type parameters are bounded as needed
+//! by the fields using them.
+
+use std::marker::PhantomData;
+use structconf::StructConf;
+
+trait Backend {}
+
+#[allow(dead_code)]
+#[derive(StructConf)]
+struct Config<T, L, B: Backend> {
+    value: T,
+    #[conf(no_file)]
+    list: Vec<L>,
+    backend: PhantomData<B>,
+}
+
+#[allow(dead_code)]
+#[derive(StructConf)]
+struct Nested<'a, C>
+where
+    C: Clone,
+{
+    #[conf(flatten)]
+    inner: C,
+    name: String,
+    lifetime: PhantomData<&'a ()>,
+}
+
+#[allow(dead_code)]
+#[derive(StructConf)]
+enum Command<S> {
+    Serve(S),
+}
+
+fn main() {}
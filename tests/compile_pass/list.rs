@@ -0,0 +1,17 @@
+//! Lists with and without delimiters.
+
+use structconf::StructConf;
+
+#[allow(dead_code)]
+#[derive(StructConf)]
+struct Config {
+    strings: Vec<String>,
+    #[conf(delimiter = ";")]
+    numbers: Vec<f64>,
+    #[conf(no_file)]
+    flags: Vec<bool>,
+    #[conf(no_short, no_long, default = "vec![1, 2, 3]")]
+    file_only: Vec<u8>,
+}
+
+fn main() {}
@@ -0,0 +1,19 @@
+//! Maps with and without a section in the config file.
+
+use std::collections::{BTreeMap, HashMap};
+use structconf::StructConf;
+
+#[allow(dead_code)]
+#[derive(StructConf)]
+struct Config {
+    #[conf(section_map = "Aliases")]
+    aliases: HashMap<String, String>,
+    #[conf(no_file)]
+    weights: BTreeMap<u8, f64>,
+    #[conf(no_short, no_long, section_map = "Hosts")]
+    hosts: HashMap<String, u16>,
+    #[conf(no_short, no_long, no_file)]
+    skipped: BTreeMap<String, bool>,
+}
+
+fn main() {}
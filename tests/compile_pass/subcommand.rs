@@ -0,0 +1,42 @@
+//! Enums whose variants are subcommands, which may be optional.
+
+use structconf::StructConf;
+
+#[allow(dead_code)]
+#[derive(StructConf)]
+struct Serve {
+    port: u16,
+}
+
+#[allow(dead_code)]
+#[derive(StructConf)]
+struct Migrate {
+    #[conf(no_short)]
+    dry_run: bool,
+}
+
+#[allow(dead_code)]
+#[derive(StructConf)]
+enum Command {
+    #[conf(help = "Starts the server")]
+    Serve(Serve),
+    #[conf(name = "db-migrate", section = "Migrations")]
+    Migrate(Migrate),
+}
+
+#[allow(dead_code)]
+#[derive(StructConf)]
+struct Config {
+    verbose: bool,
+    #[conf(subcommand)]
+    command: Command,
+}
+
+#[allow(dead_code)]
+#[derive(StructConf)]
+struct OptionalConfig {
+    #[conf(subcommand)]
+    command: Option<Command>,
+}
+
+fn main() {}
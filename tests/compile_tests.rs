@@ -7,4 +7,8 @@ fn compile_fails() {
     let t = trybuild::TestCases::new();
     t.pass("tests/compile_pass/*.rs");
     t.compile_fail("tests/compile_fail/*.rs");
+    #[cfg(feature = "regex")]
+    t.compile_fail("tests/compile_fail/regex/*.rs");
+    #[cfg(not(feature = "regex"))]
+    t.compile_fail("tests/compile_fail/no_regex/*.rs");
 }
@@ -3,15 +3,28 @@
 //! `target/tests/structconf` directory. These files should have different
 //! names to avoid conflicts.
 
+#![allow(
+    clippy::bool_assert_comparison,
+    clippy::assertions_on_constants,
+    clippy::derivable_impls,
+    clippy::unused_io_amount
+)]
+
+use std::collections::{BTreeMap, HashMap};
 use std::convert::AsRef;
 use std::default::Default;
 use std::fmt;
 use std::fs::{self, File};
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use structconf::{clap, Error, StructConf};
-use strum_macros::{Display, EnumString};
+use structconf::{clap, Error, Source, StructConf, WriteMode, WriteOptions};
+use strum::VariantNames;
+use strum_macros::{Display, EnumString, EnumVariantNames};
+
+/// The diagnostics hook is global, so the tests using it can't run at the
+/// same time.
+static HOOK_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
 
 /// `TempFile` is a very simple wrapper for automatically cleaning up files
 /// used in the tests.
@@ -53,7 +66,7 @@ impl std::ops::Deref for TempFile {
 /// Defining an enum to use in a StructConf-derived structure.
 /// The `strum` crate takes care of the automatic conversion to and from
 /// a string.
-#[derive(Debug, Clone, PartialEq, Display, EnumString)]
+#[derive(Debug, Clone, PartialEq, Display, EnumString, EnumVariantNames)]
 enum MyEnum {
     One,
     Two,
@@ -129,6 +142,389 @@ struct Config {
     option_string: Option<String>,
 }
 
+#[derive(Debug, PartialEq, StructConf)]
+#[conf(env_prefix = "STRUCTCONF_TEST_")]
+struct EnvConfig {
+    #[conf(no_short)]
+    prefixed: i32,
+    #[conf(no_short, env = "STRUCTCONF_TEST_RENAMED")]
+    custom: i32,
+    #[conf(no_short, no_env)]
+    no_env: i32,
+    #[conf(no_short, no_long, no_file, env = "STRUCTCONF_TEST_ONLY")]
+    only_env: Option<MyEnum>,
+    #[conf(no_short)]
+    flag: bool,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct DatabaseConfig {
+    #[conf(help = "The database's port")]
+    port: u16,
+    #[conf(no_short, default = "String::from(\"localhost\")")]
+    host: String,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct CommonConfig {
+    #[conf(short = "l")]
+    log_level: i32,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct NestedConfig {
+    #[conf(no_short)]
+    verbose: bool,
+    #[conf(flatten)]
+    common: CommonConfig,
+    #[conf(nested)]
+    database: DatabaseConfig,
+    #[conf(nested, long = "backup", section = "Backup")]
+    replica: DatabaseConfig,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct ClusterConfig {
+    #[conf(no_short)]
+    name: String,
+    #[conf(nested)]
+    primary: DatabaseConfig,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct DeploymentConfig {
+    #[conf(no_short)]
+    region: String,
+    #[conf(nested)]
+    cluster: ClusterConfig,
+}
+
+/// Both `log_level` collide, which is only found at runtime.
+#[derive(Debug, PartialEq, StructConf)]
+struct ConflictingConfig {
+    #[conf(no_short)]
+    log_level: i32,
+    #[conf(flatten)]
+    common: CommonConfig,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct UrlConfig {
+    #[conf(no_short, env = "STRUCTCONF_URL")]
+    url: String,
+}
+
+/// Both `url` are read from the same environment variable.
+#[derive(Debug, PartialEq, StructConf)]
+struct ConflictingEnvConfig {
+    #[conf(nested)]
+    primary: UrlConfig,
+    #[conf(nested)]
+    replica: UrlConfig,
+}
+
+/// Only one subcommand is used at a time, so they may share it.
+#[derive(Debug, PartialEq, StructConf)]
+enum UrlCommand {
+    Fetch(UrlConfig),
+    Push(UrlConfig),
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct SharedEnvConfig {
+    #[conf(subcommand)]
+    command: UrlCommand,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct ListConfig {
+    #[conf(no_short, long = "include")]
+    includes: Vec<String>,
+    #[conf(no_short, delimiter = ",")]
+    numbers: Vec<i32>,
+    #[conf(no_short, default = "vec![MyEnum::Two]", env = "STRUCTCONF_TEST_ENUMS")]
+    enums: Vec<MyEnum>,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct DelimitedConfig {
+    #[conf(no_short, delimiter = ",")]
+    names: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct DelimitedDefaultConfig {
+    #[conf(
+        no_short,
+        delimiter = ",",
+        default = "vec![\"a,b\".to_string(), \"c\".to_string()]"
+    )]
+    names: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct MapConfig {
+    #[conf(no_short, long = "alias", section_map = "Aliases")]
+    aliases: HashMap<String, String>,
+    #[conf(no_short, no_long, section_map = "Timeouts")]
+    timeouts: BTreeMap<String, u32>,
+}
+
+fn check_power_of_two(val: &u32) -> Result<(), String> {
+    if val.is_power_of_two() {
+        Ok(())
+    } else {
+        Err("not a power of two".to_string())
+    }
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct ValidationConfig {
+    #[conf(no_short, range = "1..=65535", default = "8080")]
+    port: u16,
+    #[conf(no_short, non_empty)]
+    host: Option<String>,
+    #[conf(no_short, validate = "check_power_of_two", delimiter = ",")]
+    workers: Vec<u32>,
+}
+
+#[cfg(feature = "regex")]
+#[derive(Debug, PartialEq, StructConf)]
+struct RegexConfig {
+    #[conf(no_short, regex = "[a-z]+(\\.[a-z]+)*")]
+    host: Option<String>,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+#[conf(missing_file = "error")]
+struct RequiredFileConfig {
+    value: i32,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+#[conf(missing_file = "empty")]
+struct OptionalFileConfig {
+    #[conf(default = "1")]
+    value: i32,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+#[conf(missing_file = "template")]
+struct TemplateConfig {
+    #[conf(
+        default = "30",
+        help = "The time to wait for a response from the server before giving \
+                up on the request, in seconds"
+    )]
+    timeout: u32,
+    #[conf(no_file, help = "Not in the config file")]
+    verbose: bool,
+    #[conf(section = "Server", help = "The hosts to connect to")]
+    hosts: Vec<String>,
+    #[conf(section = "Server")]
+    name: Option<String>,
+    #[conf(
+        no_short,
+        no_long,
+        section_map = "Aliases",
+        default = "vec![(\"ll\".to_string(), \"ls -l\".to_string())].into_iter().collect()",
+        help = "Custom aliases"
+    )]
+    aliases: BTreeMap<String, String>,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+#[conf(file_mode = 0o600)]
+struct PrivateConfig {
+    token: String,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+#[conf(file_mode = 0o600, missing_file = "template")]
+struct PrivateTemplateConfig {
+    token: String,
+}
+
+/// `Debug` is implemented by the derive, since the secrets are redacted.
+#[derive(PartialEq, StructConf)]
+struct SecretConfig {
+    #[conf(no_short)]
+    client_id: String,
+    #[conf(no_short, secret)]
+    client_secret: Option<String>,
+    #[conf(no_short, secret, env = "SECRET_TEST_TOKEN")]
+    refresh_token: String,
+    #[conf(no_short, secret, range = "1000..")]
+    pin: u32,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct AliasConfig {
+    #[conf(no_short, alias = "timeout", deprecated = "use `timeout_ms` instead")]
+    timeout_ms: u64,
+    #[conf(no_short, alias = "verbose", alias = "loud")]
+    debug: bool,
+    #[conf(no_short, no_file, deprecated = "it has no effect")]
+    legacy: Option<String>,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+#[conf(version = 3, migrate = "migrate_config")]
+struct VersionedConfig {
+    #[conf(no_short)]
+    timeout_ms: u64,
+    #[conf(no_short, section = "Network")]
+    host: String,
+}
+
+/// Version 2 renamed `timeout` to `timeout_secs` and moved `host` to its own
+/// section, and version 3 measured the timeout in milliseconds.
+fn migrate_config(doc: &mut structconf::ini::Ini, from: u32) -> Result<(), String> {
+    match from {
+        1 => {
+            if let Some(secs) = doc.delete_from(Some("Defaults"), "timeout") {
+                doc.with_section(Some("Defaults")).set("timeout_secs", secs);
+            }
+            if let Some(host) = doc.delete_from(Some("Defaults"), "host") {
+                doc.with_section(Some("Network")).set("host", host);
+            }
+        }
+        2 => {
+            if let Some(secs) = doc.delete_from(Some("Defaults"), "timeout_secs") {
+                let secs: u64 = secs
+                    .parse()
+                    .map_err(|_| format!("invalid timeout `{}`", secs))?;
+                doc.with_section(Some("Defaults"))
+                    .set("timeout_ms", (secs * 1000).to_string());
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+#[conf(version = 2, rewrite_migrated)]
+struct RewrittenConfig {
+    #[conf(no_short)]
+    timeout: u64,
+}
+
+#[derive(PartialEq, StructConf)]
+#[conf(deny_unknown)]
+struct StrictConfig {
+    #[conf(no_short)]
+    audiosync_calibration: i32,
+    #[conf(no_short, alias = "offset")]
+    delay: i32,
+    #[conf(no_short, section = "Network")]
+    host: Option<String>,
+    #[conf(no_short, section_map = "Commands")]
+    commands: BTreeMap<String, String>,
+    #[conf(no_short, secret, alias = "old_token")]
+    token: Option<String>,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+#[conf(warn_unknown)]
+struct LenientConfig {
+    #[conf(no_short)]
+    timeout: u64,
+}
+
+/// A type without a default value, for required fields.
+#[derive(Debug, PartialEq)]
+struct Port(u16);
+
+impl FromStr for Port {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Port)
+    }
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(PartialEq, StructConf)]
+struct RequiredConfig {
+    #[conf(
+        required,
+        short = "k",
+        env = "STRUCTCONF_REQUIRED_KEY",
+        section = "Auth"
+    )]
+    api_key: String,
+    #[conf(required, no_short, no_file)]
+    port: Port,
+    #[conf(no_short)]
+    timeout: u64,
+    #[conf(required, secret, no_short, env = "STRUCTCONF_REQUIRED_TOKEN")]
+    token: String,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct CompletionConfig {
+    #[conf(possible_values = "MyEnum::VARIANTS")]
+    mode: MyEnum,
+    #[conf(no_short)]
+    log_file: Option<PathBuf>,
+    #[conf(no_short, delimiter = ",", possible_values = "MyEnum::VARIANTS")]
+    modes: Vec<MyEnum>,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct ServeConfig {
+    #[conf(default = "8080")]
+    port: u16,
+    #[conf(no_short)]
+    host: String,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct MigrateConfig {
+    #[conf(no_short)]
+    dry_run: bool,
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+enum Command {
+    Serve(ServeConfig),
+    #[conf(section = "Migrations", help = "Migrates the database")]
+    Migrate(MigrateConfig),
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct AppConfig {
+    #[conf(no_short)]
+    verbose: bool,
+    #[conf(subcommand)]
+    command: Command,
+}
+
+trait Backend {
+    const NAME: &'static str;
+}
+
+#[derive(Debug, PartialEq)]
+struct Sqlite;
+
+impl Backend for Sqlite {
+    const NAME: &'static str = "sqlite";
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct GenericConfig<T, B: Backend> {
+    value: T,
+    #[conf(no_short)]
+    values: Vec<T>,
+    backend: std::marker::PhantomData<B>,
+}
+
 /// Reading values from the configuration file
 #[test]
 fn read_file() {
@@ -204,6 +600,276 @@ fn write_file() {
     assert_eq!(conf.combined, false); // Not written, it's the default
 }
 
+/// Only the struct's keys are updated when the layout is preserved.
+#[test]
+fn write_preserving_layout() {
+    let file = TempFile::new("write_preserving_layout.ini");
+    fs::write(
+        &file,
+        "; My settings\r
+[Defaults]\r
+# Seconds\r
+timeout : 10\r
+unknown = kept\r
+\r
+[Server]\r
+hosts = a\r
+; Extra hosts\r
+hosts = b\r
+hosts = c\r
+name = old\r
+\r
+[Aliases]\r
+la = ls -a\r
+ll = ls\r
+",
+    )
+    .unwrap();
+
+    let app = clap::App::new("test");
+    let args = TemplateConfig::parse_args_from(app, vec!["test"]);
+    let mut conf = TemplateConfig::parse_file(&args, &file).unwrap();
+    conf.timeout = 20;
+    conf.hosts = vec!["x".to_string(), "y".to_string()];
+    conf.name = None;
+    conf.aliases.remove("la");
+    conf.aliases.insert("ll".to_string(), "ls -l".to_string());
+    conf.aliases.insert("g".to_string(), "git".to_string());
+    let options = WriteOptions::new().preserve_layout(true);
+    conf.write_file_with(&file, &options).unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "; My settings\r
+[Defaults]\r
+# Seconds\r
+timeout : 20\r
+unknown = kept\r
+\r
+[Server]\r
+hosts = x\r
+; Extra hosts\r
+hosts = y\r
+\r
+[Aliases]\r
+ll = ls -l\r
+g=git
+"
+    );
+
+    // Missing sections are added at the end.
+    fs::write(&file, "; Empty").unwrap();
+    conf.write_file_with(&file, &options).unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "; Empty
+
+[Defaults]
+timeout=20
+
+[Server]
+hosts=x
+hosts=y
+
+[Aliases]
+g=git
+ll=ls -l
+"
+    );
+    let conf2 = TemplateConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(conf2, conf);
+
+    // The values are kept as they are, even if they look like a key.
+    conf.name = Some("k=k=hello".to_string());
+    conf.aliases.insert("k".to_string(), "k=k=v".to_string());
+    conf.write_file_with(&file, &options).unwrap();
+    let conf2 = TemplateConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(conf2, conf);
+
+    // Only INI files can be edited.
+    let json = TempFile::new("write_preserving_layout.json");
+    fs::write(&json, "{}").unwrap();
+    assert!(matches!(
+        conf.write_file_with(&json, &options),
+        Err(Error::Format(_))
+    ));
+}
+
+/// The values equal to their defaults or to the ones loaded may be skipped.
+#[test]
+fn write_modes() {
+    let file = TempFile::new("write_modes.ini");
+    let app = clap::App::new("test");
+    let args = TemplateConfig::parse_args_from(app, vec!["test"]);
+    let mut conf = TemplateConfig::parse_file(&args, &file).unwrap();
+
+    // Only the values that differ from their defaults are written.
+    let options = WriteOptions::new().mode(WriteMode::NonDefault);
+    conf.write_file_with(&file, &options).unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "");
+    conf.hosts = vec!["a".to_string()];
+    conf.write_file_with(&file, &options).unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "[Server]\nhosts=a\n");
+
+    // The removed values are replaced by their defaults, even if they change.
+    conf.timeout = 40;
+    conf.aliases.clear();
+    conf.write_file_with(&file, &options).unwrap();
+    conf.timeout = 30;
+    conf.write_file_with(&file, &options.clone().preserve_layout(true))
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "[Defaults]\n\n[Server]\nhosts=a\n\n[Aliases]\n"
+    );
+
+    // Only the values that changed since they were loaded are written, and
+    // the rest of the config file is kept.
+    fs::write(
+        &file,
+        "[Defaults]\ntimeout=30\n\n[Server]\nname=srv\nunknown=1\n",
+    )
+    .unwrap();
+    let mut conf = TemplateConfig::parse_file(&args, &file).unwrap();
+    conf.hosts = vec!["b".to_string()];
+    let options = WriteOptions::new().mode(WriteMode::Changed);
+    conf.write_file_with(&file, &options).unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "[Defaults]\ntimeout=30\n\n[Server]\nname=srv\nunknown=1\nhosts=b\n"
+    );
+    conf.name = None;
+    conf.write_file_with(&file, &options).unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "[Defaults]\ntimeout=30\n\n[Server]\nunknown=1\nhosts=b\n"
+    );
+
+    // The values overridden by the arguments are only kept out of the
+    // config file with a snapshot of the values loaded.
+    let args = TemplateConfig::parse_args_from(clap::App::new("test"), vec!["test", "-t", "50"]);
+    let mut conf = TemplateConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(conf.timeout, 50);
+    let options = WriteOptions::new().mode(WriteMode::Changed).loaded(&conf);
+    conf.hosts = vec!["c".to_string()];
+    conf.write_file_with(&file, &options).unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "[Defaults]\ntimeout=30\n\n[Server]\nunknown=1\nhosts=c\n"
+    );
+    let options = WriteOptions::new().mode(WriteMode::Changed);
+    conf.write_file_with(&file, &options).unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "[Defaults]\ntimeout=50\n\n[Server]\nunknown=1\nhosts=c\n"
+    );
+}
+
+/// The config file is replaced atomically, creating its parent directories
+/// and with the configured permissions.
+#[test]
+fn write_atomically() {
+    let dir = "write_atomically";
+    fs::remove_dir_all(dir).ok();
+    let file = TempFile::new("write_atomically/nested/config.ini");
+
+    let conf = PrivateConfig {
+        token: "secret".to_string(),
+    };
+    conf.write_file(&file).unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "[Defaults]\ntoken=secret\n"
+    );
+    // No temporary files are left behind.
+    assert_eq!(fs::read_dir("write_atomically/nested").unwrap().count(), 1);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |path: &str| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&file), 0o600);
+
+        // The mode can be overriden, and other files keep their permissions.
+        let options = WriteOptions::new().file_mode(0o640);
+        conf.write_file_with(&file, &options).unwrap();
+        assert_eq!(mode(&file), 0o640);
+        let other = TempFile::new("write_atomically/nested/other.ini");
+        fs::write(&other, "").unwrap();
+        fs::set_permissions(&other, fs::Permissions::from_mode(0o604)).unwrap();
+        OptionalFileConfig { value: 2 }.write_file(&other).unwrap();
+        assert_eq!(mode(&other), 0o604);
+
+        // Missing files are created with the permissions as well.
+        fs::remove_file(&file).unwrap();
+        let args = PrivateConfig::parse_args_from(clap::App::new("test"), vec!["test"]);
+        PrivateConfig::parse_file(&args, &file).unwrap();
+        assert_eq!(mode(&file), 0o600);
+
+        // And so are the templates.
+        fs::remove_file(&file).unwrap();
+        PrivateTemplateConfig::write_template(&file).unwrap();
+        assert_eq!(mode(&file), 0o600);
+        fs::remove_file(&file).unwrap();
+        let args = PrivateTemplateConfig::parse_args_from(clap::App::new("test"), vec!["test"]);
+        PrivateTemplateConfig::parse_file(&args, &file).unwrap();
+        assert_eq!(mode(&file), 0o600);
+    }
+
+    // The same file can be written from multiple threads at once.
+    let threads: Vec<_> = (0..8)
+        .map(|i| {
+            let path = file.0.clone();
+            std::thread::spawn(move || {
+                let conf = PrivateConfig {
+                    token: i.to_string(),
+                };
+                conf.write_file(&path).unwrap();
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(fs::read_dir("write_atomically/nested").unwrap().count(), 1);
+
+    drop(file);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// Only the possible values are accepted by the argument parser.
+#[test]
+fn possible_values() {
+    let app = clap::App::new("test").args(CompletionConfig::__args(&structconf::Context::root()));
+    let result = app
+        .clone()
+        .try_get_matches_from(vec!["test", "--mode", "Four"]);
+    assert_eq!(result.unwrap_err().kind(), clap::ErrorKind::InvalidValue);
+
+    // Each of the values of a list with a delimiter is checked.
+    let result = app
+        .clone()
+        .try_get_matches_from(vec!["test", "--modes", "One,Four"]);
+    assert_eq!(result.unwrap_err().kind(), clap::ErrorKind::ValueValidation);
+    let args = app.try_get_matches_from(vec!["test", "--modes", "One,Two", "--modes", "Three"]);
+    let file = TempFile::new("possible_values.ini");
+    let conf = CompletionConfig::parse_file(&args.unwrap(), &file).unwrap();
+    assert_eq!(conf.modes, [MyEnum::One, MyEnum::Two, MyEnum::Three]);
+}
+
+/// The completions include the possible values and the path hints.
+#[cfg(feature = "completions")]
+#[test]
+fn completions() {
+    use structconf::Shell;
+
+    let mut script = Vec::new();
+    CompletionConfig::completions(Shell::Zsh, "test", &mut script);
+    let script = String::from_utf8(script).unwrap();
+    assert!(script.contains("#compdef test"));
+    assert!(script.contains("'--mode=[]: :(One Two Three)'"));
+    assert!(script.contains("'--log-file=[]: :_files'"));
+}
+
 /// Making sure that the basic arguments are generated correctly.
 #[test]
 fn args() {
@@ -240,6 +906,114 @@ fn priorities() {
     // TODO
 }
 
+/// Reading values from the environment, which take priority over the config
+/// file but not over the arguments.
+#[test]
+fn env() {
+    let file = TempFile::new("env.ini");
+
+    let mut f = File::create(&file).unwrap();
+    f.write_all(
+        b"[Defaults]
+prefixed = 1
+custom = 1
+no_env = 1",
+    )
+    .unwrap();
+
+    std::env::set_var("STRUCTCONF_TEST_PREFIXED", "2");
+    std::env::set_var("STRUCTCONF_TEST_RENAMED", "2");
+    std::env::set_var("STRUCTCONF_TEST_CUSTOM", "3"); // Renamed, unused
+    std::env::set_var("STRUCTCONF_TEST_NO_ENV", "2");
+    std::env::set_var("STRUCTCONF_TEST_ONLY", "Three");
+    std::env::set_var("STRUCTCONF_TEST_FLAG", "true");
+
+    let app = clap::App::new("test");
+    let args = EnvConfig::parse_args_from(app, vec!["test", "--custom", "3"]);
+    let conf = EnvConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(conf.prefixed, 2);
+    assert_eq!(conf.custom, 3);
+    assert_eq!(conf.no_env, 1);
+    assert_eq!(conf.only_env, Some(MyEnum::Three));
+    assert_eq!(conf.flag, true);
+
+    // Invalid values are reported like the ones in the config file.
+    std::env::set_var("STRUCTCONF_TEST_PREFIXED", "not a number");
+    let app = clap::App::new("test");
+    let args = EnvConfig::parse_args_from(app, vec!["test"]);
+    match EnvConfig::parse_file(&args, &file) {
+        Err(Error::Parse(_)) => {}
+        s => panic!("parse error not returned: {:?}", s),
+    }
+}
+
+/// The source of each value is recorded, including the ones in nested
+/// structs.
+#[test]
+fn sources() {
+    let file = TempFile::new("sources.ini");
+
+    let mut f = File::create(&file).unwrap();
+    f.write_all(
+        b"[Defaults]
+log_level = 2
+[database]
+port = 1234
+[Aliases]
+ll = ls -l",
+    )
+    .unwrap();
+
+    let app = clap::App::new("test");
+    let args = NestedConfig::parse_args_from(app, vec!["test", "--verbose", "--backup-port", "1"]);
+    let (_, sources) = NestedConfig::parse_file_with_sources(&args, &file).unwrap();
+    let file_source = |section: &str, key: &str| Source::File {
+        path: file.0.clone(),
+        section: section.to_string(),
+        key: Some(key.to_string()),
+    };
+    let arg_source = |name: &str| Source::Argument {
+        name: name.to_string(),
+    };
+    assert_eq!(sources.source_of("verbose"), Some(&arg_source("--verbose")));
+    assert_eq!(
+        sources.source_of("log_level"),
+        Some(&file_source("Defaults", "log_level"))
+    );
+    assert_eq!(
+        sources.source_of("database.port"),
+        Some(&file_source("database", "port"))
+    );
+    assert_eq!(sources.source_of("database.host"), Some(&Source::Default));
+    assert_eq!(
+        sources.source_of("replica.port"),
+        Some(&arg_source("--backup-port"))
+    );
+    assert_eq!(sources.source_of("database"), None);
+    assert_eq!(sources.iter().count(), 6);
+    assert_eq!(
+        sources.explain("database.port").unwrap(),
+        "`database.port` was obtained from the key `port` in section `[database]` of \
+        `sources.ini`"
+    );
+
+    // Maps are obtained from the source with the most priority.
+    let app = clap::App::new("test");
+    let args = MapConfig::parse_args_from(app, vec!["test"]);
+    let (_, sources) = MapConfig::parse_file_with_sources(&args, &file).unwrap();
+    let expected = Source::File {
+        path: file.0.clone(),
+        section: "Aliases".to_string(),
+        key: None,
+    };
+    assert_eq!(sources.source_of("aliases"), Some(&expected));
+    assert_eq!(sources.source_of("timeouts"), Some(&Source::Default));
+    let app = clap::App::new("test");
+    let args = MapConfig::parse_args_from(app, vec!["test", "--alias", "g=git"]);
+    let (_, sources) = MapConfig::parse_file_with_sources(&args, &file).unwrap();
+    assert_eq!(sources.source_of("aliases"), Some(&arg_source("--alias")));
+}
+
 /// Testing the errors that may be thrown when parsing the config
 #[test]
 fn errors() {
@@ -256,10 +1030,45 @@ no_short = \"should be a boolean\"",
 
     let app = clap::App::new("test");
     match Config::parse(app, &file) {
-        Err(Error::Parse(_)) => assert!(true),
+        Err(Error::Parse(e)) => {
+            assert_eq!(e.field, "no_short");
+            assert_eq!(e.value, "should be a boolean");
+            assert_eq!(e.expected, "bool");
+            assert_eq!(e.line, Some(3));
+            assert_eq!(
+                e.to_string(),
+                "error: invalid value `should be a boolean` for `no_short`
+  --> errors.ini:3, key `no_short` in section `[Defaults]`
+   = expected `bool`: provided string was not `true` or `false`"
+            );
+        }
         s => assert!(false, "parse error not returned: {:?}", s),
     }
 
+    // The arguments are reported with their name.
+    let mut f = File::create(&file).unwrap();
+    f.write_all(b"").unwrap();
+    let app = clap::App::new("test");
+    let args = Config::parse_args_from(app, vec!["test", "--no-file", "abc"]);
+    match Config::parse_file(&args, &file) {
+        Err(Error::Parse(e)) => {
+            assert_eq!(
+                e.source,
+                Source::Argument {
+                    name: "--no-file".to_string()
+                }
+            );
+            assert_eq!(e.line, None);
+            assert_eq!(
+                e.to_string(),
+                "error: invalid value `abc` for `no_file`
+  --> command-line argument `--no-file`
+   = expected `i32`: invalid digit found in string"
+            );
+        }
+        s => panic!("parse error not returned: {:?}", s),
+    }
+
     // Checking errors when creating the config file. The root directory
     // should throw an IO error because it's a directory, and possibly
     // because of invalid permissions.
@@ -341,3 +1150,1608 @@ fn defaults() {
     assert_eq!(conf.someenum, Default::default());
     assert_eq!(conf.astruct, Default::default());
 }
+
+/// Structs may be nested into others, with their arguments prefixed and
+/// their options in a different section, or flattened into them.
+#[test]
+fn nested() {
+    let file = TempFile::new("nested.ini");
+
+    let mut f = File::create(&file).unwrap();
+    f.write_all(
+        b"[Defaults]
+verbose = true
+log_level = 3
+
+[database]
+port = 1234
+host = remote
+
+[Backup]
+port = 4321",
+    )
+    .unwrap();
+
+    let app = clap::App::new("test");
+    let args = vec!["test", "-l", "4", "--backup-port", "5678"];
+    let args = NestedConfig::parse_args_from(app, args);
+    let mut conf = NestedConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(conf.verbose, true);
+    assert_eq!(conf.common.log_level, 4);
+    assert_eq!(
+        conf.database,
+        DatabaseConfig {
+            port: 1234,
+            host: String::from("remote"),
+        }
+    );
+    assert_eq!(
+        conf.replica,
+        DatabaseConfig {
+            port: 5678,
+            host: String::from("localhost"),
+        }
+    );
+
+    // Writing it back into the same sections.
+    conf.database.host = String::from("other");
+    conf.write_file(&file).unwrap();
+    let app = clap::App::new("test");
+    let args = NestedConfig::parse_args_from(app, vec!["test"]);
+    let read = NestedConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(read, conf);
+    let contents = fs::read_to_string(&file).unwrap();
+    assert!(contents.contains("[database]"));
+    assert!(contents.contains("[Backup]"));
+}
+
+/// The options of flattened structs can't collide with the ones in the
+/// parent.
+#[test]
+fn nested_conflicts() {
+    let file = TempFile::new("nested_conflicts.ini");
+    let conflict = |name: &str| format!("The {} is used by both `log_level` and `log_level`", name);
+
+    match ConflictingConfig::parse(clap::App::new("test"), &file) {
+        Err(e @ Error::Conflict { .. }) => {
+            assert_eq!(e.to_string(), conflict("argument `--log-level`"))
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let panic = std::panic::catch_unwind(|| {
+        ConflictingConfig::parse_args_from(clap::App::new("test"), vec!["test"])
+    })
+    .unwrap_err();
+    assert_eq!(
+        panic.downcast_ref::<String>(),
+        Some(&conflict("argument `--log-level`"))
+    );
+
+    let args = clap::App::new("test").get_matches_from(vec!["test"]);
+    match ConflictingConfig::parse_file(&args, &file) {
+        Err(e @ Error::Conflict { .. }) => assert_eq!(
+            e.to_string(),
+            conflict("key `log_level` in section `[Defaults]`")
+        ),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // The environment variables of nested structs aren't prefixed.
+    let app = clap::App::new("test");
+    let args = ConflictingEnvConfig::parse_args_from(app, vec!["test"]);
+    match ConflictingEnvConfig::parse_file(&args, &file) {
+        Err(e @ Error::Conflict { .. }) => assert_eq!(
+            e.to_string(),
+            "The environment variable `STRUCTCONF_URL` is used by both \
+             `primary.url` and `replica.url`"
+        ),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let (_, errors) = ConflictingEnvConfig::parse_file_all_errors(&args, &file);
+    assert!(matches!(errors.as_slice(), [Error::Conflict { .. }, ..]));
+
+    let app = clap::App::new("test");
+    let args = SharedEnvConfig::parse_args_from(app, vec!["test", "push", "--url", "b"]);
+    let conf = SharedEnvConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(
+        conf.command,
+        UrlCommand::Push(UrlConfig {
+            url: "b".to_string()
+        })
+    );
+    let subcommands = SharedEnvConfig::options()
+        .into_iter()
+        .map(|opt| opt.subcommands)
+        .collect::<Vec<_>>();
+    assert_eq!(subcommands, vec![vec!["fetch"], vec!["push"]]);
+}
+
+/// Lists may be repeated or use a delimiter.
+#[test]
+fn lists() {
+    let file = TempFile::new("lists.ini");
+
+    let mut f = File::create(&file).unwrap();
+    f.write_all(
+        b"[Defaults]
+includes = a
+includes = b
+numbers = 1, 2,3",
+    )
+    .unwrap();
+
+    let app = clap::App::new("test");
+    let args = ListConfig::parse_args_from(app, vec!["test"]);
+    let conf = ListConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(conf.includes, vec!["a", "b"]);
+    assert_eq!(conf.numbers, vec![1, 2, 3]);
+    assert_eq!(conf.enums, vec![MyEnum::Two]);
+
+    // The arguments replace the values in the config file.
+    std::env::set_var("STRUCTCONF_TEST_ENUMS", "One,Three");
+    let app = clap::App::new("test");
+    let args = vec![
+        "test",
+        "--include",
+        "c",
+        "--include",
+        "d,e",
+        "--numbers",
+        "4,5",
+        "--numbers",
+        "6",
+    ];
+    let args = ListConfig::parse_args_from(app, args);
+    let mut conf = ListConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(conf.includes, vec!["c", "d,e"]);
+    assert_eq!(conf.numbers, vec![4, 5, 6]);
+    assert_eq!(conf.enums, vec![MyEnum::One, MyEnum::Three]);
+    std::env::remove_var("STRUCTCONF_TEST_ENUMS");
+
+    // Writing them back, including an empty list that would otherwise be
+    // replaced by its default value.
+    conf.enums = Vec::new();
+    conf.write_file(&file).unwrap();
+    let app = clap::App::new("test");
+    let args = ListConfig::parse_args_from(app, vec!["test"]);
+    let read = ListConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(read, conf);
+    let contents = fs::read_to_string(&file).unwrap();
+    assert!(contents.contains("numbers=4,5,6"));
+
+    // Invalid values in the list are reported.
+    let mut f = File::create(&file).unwrap();
+    f.write_all(b"[Defaults]\nnumbers = 1,a").unwrap();
+    let app = clap::App::new("test");
+    let args = ListConfig::parse_args_from(app, vec!["test"]);
+    match ListConfig::parse_file(&args, &file) {
+        Err(Error::Parse(_)) => {}
+        s => panic!("parse error not returned: {:?}", s),
+    }
+}
+
+/// The delimiters inside the values of a list are escaped when writing it,
+/// so that they're read back the same.
+#[test]
+fn lists_escaped() {
+    let file = TempFile::new("lists_escaped.ini");
+    let conf = DelimitedConfig {
+        names: vec![
+            "Doe, John".to_string(),
+            "C:\\dir\\".to_string(),
+            "plain".to_string(),
+        ],
+    };
+    conf.write_file(&file).unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "[Defaults]\nnames=Doe\\\\, John,C:\\\\\\\\dir\\\\\\\\,plain\n"
+    );
+    let args = DelimitedConfig::parse_args_from(clap::App::new("test"), vec!["test"]);
+    assert_eq!(DelimitedConfig::parse_file(&args, &file).unwrap(), conf);
+
+    // In INI files, the backslashes are escaped themselves.
+    fs::write(&file, "[Defaults]\nnames = a\\\\,b, c\n").unwrap();
+    assert_eq!(
+        DelimitedConfig::parse_file(&args, &file).unwrap().names,
+        ["a,b", "c"]
+    );
+
+    // In the arguments, a single backslash is enough.
+    fs::remove_file(&file).unwrap();
+    let args = vec!["test", "--names", "a\\,b,c", "--names", "d"];
+    let args = DelimitedConfig::parse_args_from(clap::App::new("test"), args);
+    assert_eq!(
+        DelimitedConfig::parse_file(&args, &file).unwrap().names,
+        ["a,b", "c", "d"]
+    );
+}
+
+/// Multiple layers of config files, where the later ones replace the keys
+/// in the earlier ones.
+#[test]
+fn layers() {
+    let system = TempFile::new("layers_system.ini");
+    let user = TempFile::new("layers_user.ini");
+    let missing = TempFile::new("layers_missing.ini");
+
+    let mut f = File::create(&system).unwrap();
+    f.write_all(
+        b"[Defaults]
+includes = a
+includes = b
+numbers = 1,2
+[Aliases]
+ll = ls -l
+gs = git status",
+    )
+    .unwrap();
+    let mut f = File::create(&user).unwrap();
+    f.write_all(
+        b"[Defaults]
+includes = c
+[Aliases]
+gs = git status -s",
+    )
+    .unwrap();
+
+    let app = clap::App::new("test");
+    let args = ListConfig::parse_args_from(app, vec!["test", "--numbers", "3"]);
+    let layers = [system.as_ref(), missing.as_ref(), user.as_ref()];
+    let (conf, sources) = ListConfig::parse_file_layers_with_sources(&args, &layers).unwrap();
+    assert_eq!(conf.includes, vec!["c"]);
+    assert_eq!(conf.numbers, vec![3]);
+    assert_eq!(conf.enums, vec![MyEnum::Two]);
+    let expected = Source::File {
+        path: user.0.clone(),
+        section: "Defaults".to_string(),
+        key: Some("includes".to_string()),
+    };
+    assert_eq!(sources.source_of("includes"), Some(&expected));
+
+    // Missing layers aren't created.
+    assert!(!Path::new(&missing.0).exists());
+
+    // The entries in maps are merged.
+    let app = clap::App::new("test");
+    let args = MapConfig::parse_args_from(app, vec!["test"]);
+    let conf = MapConfig::parse_file_layers(&args, &layers).unwrap();
+    assert_eq!(conf.aliases.len(), 2);
+    assert_eq!(conf.aliases["ll"], "ls -l");
+    assert_eq!(conf.aliases["gs"], "git status -s");
+
+    // Without any layers, the default values are used.
+    let app = clap::App::new("test");
+    let args = ListConfig::parse_args_from(app, vec!["test"]);
+    let conf = ListConfig::parse_file_layers(&args, &[missing.as_ref()]).unwrap();
+    assert_eq!(conf.includes, Vec::<String>::new());
+}
+
+/// Maps take all the keys in their section, and the arguments are merged
+/// into them.
+#[test]
+fn maps() {
+    let file = TempFile::new("maps.ini");
+
+    let mut f = File::create(&file).unwrap();
+    f.write_all(
+        b"[Aliases]
+ll = ls -l
+gs = git status
+[Timeouts]
+example.com = 30",
+    )
+    .unwrap();
+
+    let app = clap::App::new("test");
+    let args = vec!["test", "--alias", "gs=git status -s", "--alias", "g=git"];
+    let args = MapConfig::parse_args_from(app, args);
+    let mut conf = MapConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(conf.aliases.len(), 3);
+    assert_eq!(conf.aliases["ll"], "ls -l");
+    assert_eq!(conf.aliases["gs"], "git status -s");
+    assert_eq!(conf.aliases["g"], "git");
+    assert_eq!(
+        conf.timeouts.into_iter().collect::<Vec<_>>(),
+        vec![("example.com".to_string(), 30)]
+    );
+
+    // Writing them back, including an empty map.
+    conf.timeouts = BTreeMap::new();
+    conf.write_file(&file).unwrap();
+    let app = clap::App::new("test");
+    let args = MapConfig::parse_args_from(app, vec!["test"]);
+    let read = MapConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(read, conf);
+    let contents = fs::read_to_string(&file).unwrap();
+    assert!(contents.contains("[Timeouts]"));
+
+    // Invalid values and arguments without a key are reported.
+    let mut f = File::create(&file).unwrap();
+    f.write_all(b"[Timeouts]\nexample.com = soon").unwrap();
+    let app = clap::App::new("test");
+    let args = MapConfig::parse_args_from(app, vec!["test"]);
+    match MapConfig::parse_file(&args, &file) {
+        Err(Error::Parse(_)) => {}
+        s => panic!("parse error not returned: {:?}", s),
+    }
+    let app = clap::App::new("test");
+    let args = MapConfig::parse_args_from(app, vec!["test", "--alias", "ls"]);
+    match MapConfig::parse_file(&args, &file) {
+        Err(Error::Parse(_)) => {}
+        s => panic!("parse error not returned: {:?}", s),
+    }
+}
+
+/// The values are validated whichever source they came from.
+#[test]
+fn validation() {
+    let file = TempFile::new("validation.ini");
+
+    let parse = |file_contents: &str, args: Vec<&str>| {
+        let mut f = File::create(&file).unwrap();
+        f.write_all(file_contents.as_bytes()).unwrap();
+        let app = clap::App::new("test");
+        let mut full_args = vec!["test"];
+        full_args.extend(args);
+        let args = ValidationConfig::parse_args_from(app, full_args);
+        ValidationConfig::parse_file(&args, &file)
+    };
+    let check_err = |res: Result<ValidationConfig, Error>, exp_field, exp_value| match res {
+        Err(Error::Validation {
+            field,
+            origin,
+            value,
+            ..
+        }) => {
+            assert_eq!(field, exp_field);
+            assert_eq!(value, exp_value);
+            assert_ne!(*origin, Source::Default);
+        }
+        s => panic!("validation error not returned: {:?}", s),
+    };
+
+    let conf = parse(
+        "[Defaults]\nport = 1234\nhost = example.com\nworkers = 2,4",
+        vec![],
+    )
+    .unwrap();
+    assert_eq!(conf.port, 1234);
+    assert_eq!(conf.host, Some("example.com".to_string()));
+    assert_eq!(conf.workers, vec![2, 4]);
+
+    // The default values aren't validated.
+    let conf = parse("", vec![]).unwrap();
+    assert_eq!(conf.port, 8080);
+
+    check_err(parse("[Defaults]\nport = 0", vec![]), "port", "0");
+    check_err(parse("", vec!["--port", "0"]), "port", "0");
+    check_err(parse("[Defaults]\nhost = ", vec![]), "host", "");
+    check_err(parse("", vec!["--host", " "]), "host", " ");
+    check_err(parse("", vec!["--workers", "2,3"]), "workers", "3");
+
+    // Parsing errors are still reported as such.
+    match parse("[Defaults]\nport = abc", vec![]) {
+        Err(Error::Parse(_)) => {}
+        s => panic!("parse error not returned: {:?}", s),
+    }
+}
+
+/// The raw values must match the entire regex, whichever source they come
+/// from.
+#[cfg(feature = "regex")]
+#[test]
+fn regex() {
+    let file = TempFile::new("regex.ini");
+
+    let parse = |file_contents: &str, args: Vec<&str>| {
+        fs::write(&file, file_contents).unwrap();
+        let app = clap::App::new("test");
+        let mut full_args = vec!["test"];
+        full_args.extend(args);
+        let args = RegexConfig::parse_args_from(app, full_args);
+        RegexConfig::parse_file(&args, &file)
+    };
+
+    // The compiled regex is reused after the first value.
+    let conf = parse("[Defaults]\nhost = example.com", vec![]).unwrap();
+    assert_eq!(conf.host, Some("example.com".to_string()));
+    let conf = parse("", vec!["--host", "localhost"]).unwrap();
+    assert_eq!(conf.host, Some("localhost".to_string()));
+
+    match parse("", vec!["--host", "Example"]) {
+        Err(e @ Error::Validation { .. }) => assert_eq!(
+            e.to_string(),
+            "Invalid value `Example` for `host` from the command-line argument \
+             `--host`: the value must match `[a-z]+(\\.[a-z]+)*`"
+        ),
+        s => panic!("validation error not returned: {:?}", s),
+    }
+    match parse("[Defaults]\nhost = example.", vec![]) {
+        Err(Error::Validation { origin, value, .. }) => {
+            assert_eq!(value, "example.");
+            assert_eq!(
+                *origin,
+                Source::File {
+                    path: file.0.clone(),
+                    section: "Defaults".to_string(),
+                    key: Some("host".to_string()),
+                }
+            );
+        }
+        s => panic!("validation error not returned: {:?}", s),
+    }
+
+    assert!(RegexConfig::json_schema().contains(r#""pattern": "^(?:[a-z]+(\\.[a-z]+)*)$""#));
+}
+
+/// The config file may not be created when it's missing.
+#[test]
+fn missing_file() {
+    use std::sync::{Arc, Mutex};
+    use structconf::diagnostics::{self, Diagnostic};
+
+    let created = TempFile::new("missing_created.ini");
+    let missing = TempFile::new("missing_empty.ini");
+
+    let app = clap::App::new("test");
+    let args = RequiredFileConfig::parse_args_from(app, vec!["test"]);
+    match RequiredFileConfig::parse_file(&args, &missing) {
+        Err(Error::NotFound(path)) => assert_eq!(path, missing.0),
+        s => panic!("not found error not returned: {:?}", s),
+    }
+    let (_, errors) = RequiredFileConfig::parse_file_all_errors(&args, &missing);
+    assert_eq!(errors.len(), 1);
+
+    let app = clap::App::new("test");
+    let args = OptionalFileConfig::parse_args_from(app, vec!["test"]);
+    let conf = OptionalFileConfig::parse_file(&args, &missing).unwrap();
+    assert_eq!(conf.value, 1);
+    assert!(!Path::new(&missing.0).exists());
+
+    // By default, the file is created and the notice is sent to the hook.
+    // Other tests may run at the same time, so only this file's notice is
+    // checked.
+    let _lock = HOOK_LOCK.lock().unwrap();
+    let notices = Arc::new(Mutex::new(Vec::new()));
+    let notices_hook = Arc::clone(&notices);
+    diagnostics::set_hook(move |d| notices_hook.lock().unwrap().push(d.clone()));
+    let app = clap::App::new("test");
+    let args = Config::parse_args_from(app, vec!["test"]);
+    Config::parse_file(&args, &created).unwrap();
+    diagnostics::reset_hook();
+    assert!(Path::new(&created.0).exists());
+    let expected = Diagnostic::CreatedFile {
+        path: created.0.clone(),
+    };
+    assert!(notices.lock().unwrap().contains(&expected));
+}
+
+/// The templates include every option in the config file, commented out.
+#[test]
+fn templates() {
+    let file = TempFile::new("templates.ini");
+
+    TemplateConfig::write_template(&file).unwrap();
+    let contents = fs::read_to_string(&file).unwrap();
+    assert_eq!(
+        contents,
+        "[Defaults]
+; The time to wait for a response from the server before giving up on the
+; request, in seconds
+; timeout = 30
+
+[Server]
+; The hosts to connect to
+; hosts =
+
+; name =
+
+; Custom aliases
+; [Aliases]
+; ll = ls -l
+"
+    );
+
+    // The template doesn't change the default values.
+    let app = clap::App::new("test");
+    let args = TemplateConfig::parse_args_from(app, vec!["test"]);
+    let conf = TemplateConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(conf.timeout, 30);
+    assert_eq!(conf.aliases.len(), 1);
+
+    // It's used to create missing files.
+    fs::remove_file(&file).unwrap();
+    TemplateConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), contents);
+
+    // Nested structs are included as well.
+    NestedConfig::write_template(&file).unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "[Defaults]
+; verbose = false
+
+; log_level = 0
+
+[database]
+; The database's port
+; port = 0
+
+; host = localhost
+
+[Backup]
+; The database's port
+; port = 0
+
+; host = localhost
+"
+    );
+
+    // Only INI files are supported.
+    match TemplateConfig::write_template("template.json") {
+        Err(Error::Format(_)) => {}
+        s => panic!("format error not returned: {:?}", s),
+    }
+}
+
+/// The information about the options is available at runtime.
+#[test]
+fn options() {
+    use structconf::meta::ValueKind;
+
+    let options = NestedConfig::options();
+    let fields = options
+        .iter()
+        .map(|opt| opt.field.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        fields,
+        vec![
+            "verbose",
+            "log_level",
+            "database.port",
+            "database.host",
+            "replica.port",
+            "replica.host"
+        ]
+    );
+    let port = &options[4];
+    assert_eq!(port.ty, "u16");
+    assert_eq!(port.kind, ValueKind::Single);
+    assert_eq!(port.help.as_deref(), Some("The database's port"));
+    assert_eq!(port.long.as_deref(), Some("backup-port"));
+    assert_eq!(port.short, None);
+    assert_eq!(port.section.as_deref(), Some("Backup"));
+    assert_eq!(port.key.as_deref(), Some("port"));
+    assert_eq!(port.default, vec!["0"]);
+    assert_eq!(options[0].kind, ValueKind::Flag);
+    assert_eq!(options[1].short, Some('l'));
+
+    let options = TemplateConfig::options();
+    assert_eq!(options[1].section, None);
+    assert_eq!(options[1].default, Vec::<String>::new());
+    assert_eq!(options[2].kind, ValueKind::List);
+    assert_eq!(options[4].kind, ValueKind::Map);
+    assert_eq!(options[4].key, None);
+}
+
+/// The man page and the Markdown reference cover every option.
+#[test]
+fn docs() {
+    use structconf::meta;
+
+    let options = TemplateConfig::options();
+    let page = meta::man_page("my-app", "does things", &options);
+    assert!(page.starts_with(".TH MY\\-APP 1\n.SH NAME\nmy\\-app \\- does things\n"));
+    assert!(page.contains(
+        ".TP
+\\fB\\-t\\fR, \\fB\\-\\-timeout\\fR \\fIVALUE\\fR
+The time to wait for a response from the server before giving up on the request, in seconds
+.br
+Config file: \\fBtimeout\\fR in \\fB[Defaults]\\fR
+.br
+Type: \\fBu32\\fR
+.br
+Default: \\fB30\\fR
+"
+    ));
+    assert!(page.contains(".TP\n\\fBaliases\\fR\nCustom aliases\n"));
+    assert!(page.contains(".SH FILES\n"));
+    assert!(page.contains("; ll = ls \\-l\n.fi\n.RE\n"));
+
+    let reference = meta::markdown("my-app", "does things", &options);
+    assert!(reference.starts_with("# my-app\n\ndoes things\n\n## Options\n"));
+    assert!(reference.contains(
+        "### `hosts`
+
+The hosts to connect to
+
+| | |
+| --- | --- |
+| Arguments | `-h <VALUE>`, `--hosts <VALUE>` |
+| Config file | `hosts` in `[Server]` |
+| Type | list of `String` |
+"
+    ));
+    assert!(reference.contains("| Arguments | `-v`, `--verbose` |\n| Type | `bool` |\n"));
+    assert!(reference.ends_with(&format!("```ini\n{}```\n", meta::template(&options))));
+}
+
+/// The JSON Schema describes the config file with the validation
+/// constraints.
+#[test]
+fn json_schema() {
+    assert_eq!(
+        ValidationConfig::json_schema(),
+        r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "type": "object",
+  "properties": {
+    "Defaults": {
+      "type": "object",
+      "properties": {
+        "port": {
+          "type": "integer",
+          "minimum": 1,
+          "maximum": 65535,
+          "default": 8080
+        },
+        "host": {
+          "type": "string",
+          "pattern": "\\S"
+        },
+        "workers": {
+          "type": "array",
+          "items": {
+            "type": "integer"
+          },
+          "default": []
+        }
+      }
+    }
+  }
+}
+"#
+    );
+
+    let schema = TemplateConfig::json_schema();
+    assert!(schema.contains(
+        r#"    "Aliases": {
+      "description": "Custom aliases",
+      "type": "object",
+      "additionalProperties": {
+        "type": "string"
+      },
+      "default": {
+        "ll": "ls -l"
+      }
+    }"#
+    ));
+    assert!(!schema.contains("verbose"));
+
+    // The escaped delimiters in the default values are kept.
+    assert_eq!(
+        DelimitedDefaultConfig::json_schema(),
+        r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "type": "object",
+  "properties": {
+    "Defaults": {
+      "type": "object",
+      "properties": {
+        "names": {
+          "type": "array",
+          "items": {
+            "type": "string"
+          },
+          "default": ["a,b", "c"]
+        }
+      }
+    }
+  }
+}
+"#
+    );
+
+    // The sections of nested structs are nested objects.
+    assert_eq!(
+        DeploymentConfig::json_schema(),
+        r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "type": "object",
+  "properties": {
+    "Defaults": {
+      "type": "object",
+      "properties": {
+        "region": {
+          "type": "string",
+          "default": ""
+        }
+      }
+    },
+    "cluster": {
+      "type": "object",
+      "properties": {
+        "name": {
+          "type": "string",
+          "default": ""
+        },
+        "primary": {
+          "type": "object",
+          "properties": {
+            "port": {
+              "description": "The database's port",
+              "type": "integer",
+              "default": 0
+            },
+            "host": {
+              "type": "string",
+              "default": "localhost"
+            }
+          }
+        }
+      }
+    }
+  }
+}
+"#
+    );
+}
+
+/// Enums are subcommands, with their own arguments and section.
+#[test]
+fn subcommands() {
+    let file = TempFile::new("subcommands.ini");
+    fs::write(
+        &file,
+        "[serve]\nport = 9000\nhost = local\n\n[Migrations]\ndry_run = true\n",
+    )
+    .unwrap();
+
+    let app = clap::App::new("test");
+    let args = vec!["test", "--verbose", "serve", "--port", "9001"];
+    let args = AppConfig::parse_args_from(app, args);
+    let (conf, sources) = AppConfig::parse_file_with_sources(&args, &file).unwrap();
+    assert_eq!(
+        conf,
+        AppConfig {
+            verbose: true,
+            command: Command::Serve(ServeConfig {
+                port: 9001,
+                host: "local".to_string(),
+            }),
+        }
+    );
+    assert_eq!(
+        sources.source_of("serve.port"),
+        Some(&Source::Argument {
+            name: "--port".to_string()
+        })
+    );
+
+    let app = clap::App::new("test");
+    let args = AppConfig::parse_args_from(app, vec!["test", "migrate"]);
+    let mut conf = AppConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(
+        conf.command,
+        Command::Migrate(MigrateConfig { dry_run: true })
+    );
+
+    // Only the subcommand in use is written.
+    conf.command = Command::Migrate(MigrateConfig { dry_run: false });
+    let options = WriteOptions::new().preserve_layout(true);
+    conf.write_file_with(&file, &options).unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "[serve]\nport = 9000\nhost = local\n\n[Migrations]\ndry_run = false\n\n\
+         [Defaults]\nverbose=false\n"
+    );
+
+    // The options of every subcommand are available.
+    let fields = AppConfig::options()
+        .into_iter()
+        .map(|opt| (opt.field, opt.section))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        fields,
+        vec![
+            ("verbose".to_string(), Some("Defaults".to_string())),
+            ("serve.port".to_string(), Some("serve".to_string())),
+            ("serve.host".to_string(), Some("serve".to_string())),
+            (
+                "migrate.dry_run".to_string(),
+                Some("Migrations".to_string())
+            ),
+        ]
+    );
+
+    // The enum can be used by itself, in which case the subcommand is
+    // required.
+    let result = Command::parse_file(&clap::ArgMatches::default(), &file);
+    assert!(matches!(result, Err(Error::MissingSubcommand)));
+}
+
+/// Secrets are redacted, skipped when writing, and may be read from files.
+#[test]
+fn secrets() {
+    let file = TempFile::new("secrets.ini");
+    let secret_file = TempFile::new("secrets_client.txt");
+    let token_file = TempFile::new("secrets_token.txt");
+    fs::write(&secret_file, "hunter2\n").unwrap();
+    fs::write(&token_file, "abcdef").unwrap();
+    fs::write(
+        &file,
+        format!(
+            "[Defaults]\nclient_id = me\nrefresh_token_file = {}\npin = 1234\n",
+            &*token_file
+        ),
+    )
+    .unwrap();
+
+    let app = clap::App::new("test");
+    let args = vec!["test", "--client-secret-file", &secret_file];
+    let args = SecretConfig::parse_args_from(app, args);
+    let (conf, sources) = SecretConfig::parse_file_with_sources(&args, &file).unwrap();
+    assert_eq!(conf.client_secret.as_deref(), Some("hunter2"));
+    assert_eq!(conf.refresh_token, "abcdef");
+    assert_eq!(conf.pin, 1234);
+    assert_eq!(
+        sources.source_of("client_secret"),
+        Some(&Source::Argument {
+            name: "--client-secret-file".to_string()
+        })
+    );
+    assert_eq!(
+        sources.source_of("refresh_token"),
+        Some(&Source::File {
+            path: file.to_string(),
+            section: "Defaults".to_string(),
+            key: Some("refresh_token_file".to_string()),
+        })
+    );
+
+    // The environment variable has its own file as well.
+    std::env::set_var("SECRET_TEST_TOKEN_FILE", &*secret_file);
+    let conf = SecretConfig::parse_file(&args, &file).unwrap();
+    std::env::remove_var("SECRET_TEST_TOKEN_FILE");
+    assert_eq!(conf.refresh_token, "hunter2");
+
+    // They're redacted in `Debug` and in the errors.
+    assert_eq!(
+        format!("{:?}", conf),
+        "SecretConfig { client_id: \"me\", client_secret: Some(<redacted>), \
+         refresh_token: <redacted>, pin: <redacted> }"
+    );
+    fs::write(&file, "[Defaults]\npin = 12\n").unwrap();
+    let args = SecretConfig::parse_args_from(clap::App::new("test"), vec!["test"]);
+    match SecretConfig::parse_file(&args, &file) {
+        Err(Error::Validation { field, value, .. }) => {
+            assert_eq!(field, "pin");
+            assert_eq!(value, "<redacted>");
+        }
+        _ => panic!("expected a validation error"),
+    }
+    fs::write(&file, "[Defaults]\npin = 12ab\n").unwrap();
+    let err = SecretConfig::parse_file(&args, &file).unwrap_err();
+    assert!(!err.to_string().contains("12ab"));
+    fs::write(&file, "[Defaults]\npin_file = secrets_missing.txt\n").unwrap();
+    let err = SecretConfig::parse_file(&args, &file).unwrap_err();
+    assert!(matches!(err, Error::SecretFile { field, .. } if field == "pin"));
+
+    // They aren't written, but the ones in the existing file are kept.
+    let mut conf = SecretConfig {
+        client_id: "me".to_string(),
+        client_secret: Some("hunter2".to_string()),
+        refresh_token: "abcdef".to_string(),
+        pin: 1234,
+    };
+    fs::write(&file, "[Defaults]\npin = 4321\n").unwrap();
+    conf.write_file(&file).unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "[Defaults]\nclient_id=me\npin=4321\n"
+    );
+
+    // And so are the files they're read from.
+    fs::write(
+        &file,
+        format!("[Defaults]\nrefresh_token_file = {}\n", &*token_file),
+    )
+    .unwrap();
+    conf.write_file(&file).unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        format!(
+            "[Defaults]\nclient_id=me\nrefresh_token_file={}\n",
+            &*token_file
+        )
+    );
+    let args = SecretConfig::parse_args_from(clap::App::new("test"), vec!["test"]);
+    let reloaded = SecretConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(reloaded.refresh_token, "abcdef");
+
+    // Unless they're explicitly allowed, in which case only the owner can
+    // read the file.
+    conf.pin = 1111;
+    let options = WriteOptions::new().secrets(true);
+    conf.write_file_with(&file, &options).unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "[Defaults]\nclient_id=me\nclient_secret=hunter2\nrefresh_token=abcdef\npin=1111\n"
+    );
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&file).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o600);
+    }
+}
+
+/// Older config files are migrated to the struct's version before reading
+/// the fields, and the version is written along with them.
+#[test]
+fn versions() {
+    use std::sync::{Arc, Mutex};
+    use structconf::diagnostics::{self, Diagnostic};
+
+    let file = TempFile::new("versions.ini");
+    let original = "[Defaults]\ntimeout = 2\nhost = example.com\n";
+    fs::write(&file, original).unwrap();
+
+    let _lock = HOOK_LOCK.lock().unwrap();
+    let notices = Arc::new(Mutex::new(Vec::new()));
+    let notices_hook = Arc::clone(&notices);
+    diagnostics::set_hook(move |d| notices_hook.lock().unwrap().push(d.clone()));
+    let args = VersionedConfig::parse_args_from(clap::App::new("test"), vec!["test"]);
+
+    let conf = VersionedConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(
+        conf,
+        VersionedConfig {
+            timeout_ms: 2000,
+            host: "example.com".to_string(),
+        }
+    );
+    assert_eq!(fs::read_to_string(&file).unwrap(), original);
+    conf.write_file(&file).unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "[Defaults]\ntimeout_ms=2000\n\n[Network]\nhost=example.com\n\n\
+         [Metadata]\nversion=3\n"
+    );
+    assert_eq!(VersionedConfig::parse_file(&args, &file).unwrap(), conf);
+
+    // Only the steps after the file's version are run.
+    fs::write(
+        &file,
+        "[Metadata]\nversion = 2\n[Defaults]\ntimeout_secs = 1\n",
+    )
+    .unwrap();
+    assert_eq!(
+        VersionedConfig::parse_file(&args, &file)
+            .unwrap()
+            .timeout_ms,
+        1000
+    );
+    diagnostics::reset_hook();
+    assert_eq!(
+        *notices.lock().unwrap(),
+        [
+            Diagnostic::Migrated {
+                path: file.to_string(),
+                from: 1,
+                to: 3,
+            },
+            Diagnostic::Migrated {
+                path: file.to_string(),
+                from: 2,
+                to: 3,
+            },
+        ]
+    );
+
+    fs::write(
+        &file,
+        "[Metadata]\nversion = 2\n[Defaults]\ntimeout_secs = abc\n",
+    )
+    .unwrap();
+    match VersionedConfig::parse_file(&args, &file) {
+        Err(Error::Migration { from, reason, .. }) => {
+            assert_eq!(from, 2);
+            assert_eq!(reason, "invalid timeout `abc`");
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    fs::write(&file, "[Metadata]\nversion = 4\n").unwrap();
+    let (_, errors) = VersionedConfig::parse_file_all_errors(&args, &file);
+    assert!(matches!(errors[..], [Error::Migration { from: 4, .. }]));
+
+    // The version is written in every mode, so the files aren't migrated
+    // again when they're reloaded.
+    let migrated = Arc::new(Mutex::new(Vec::new()));
+    let migrated_hook = Arc::clone(&migrated);
+    diagnostics::set_hook(move |d| migrated_hook.lock().unwrap().push(d.clone()));
+    for mode in [WriteMode::NonDefault, WriteMode::Changed] {
+        fs::write(&file, "[Defaults]\ntimeout = 2\n").unwrap();
+        let conf = VersionedConfig::parse_file(&args, &file).unwrap();
+        conf.write_file_with(&file, &WriteOptions::new().mode(mode))
+            .unwrap();
+        assert!(fs::read_to_string(&file)
+            .unwrap()
+            .contains("[Metadata]\nversion=3\n"));
+        migrated.lock().unwrap().clear();
+        assert_eq!(VersionedConfig::parse_file(&args, &file).unwrap(), conf);
+        assert!(migrated.lock().unwrap().is_empty());
+    }
+
+    // The missing files and templates are created with the version.
+    let file = TempFile::new("versions_rewritten.ini");
+    let args = RewrittenConfig::parse_args_from(clap::App::new("test"), vec!["test"]);
+    diagnostics::set_hook(|_| {});
+    RewrittenConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "[Metadata]\nversion=2\n"
+    );
+    RewrittenConfig::write_template(&file).unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "[Metadata]\nversion = 2\n\n[Defaults]\n; timeout = 0\n"
+    );
+
+    // Without migrations, only the version is updated.
+    fs::write(&file, "[Defaults]\ntimeout = 5\n").unwrap();
+    assert_eq!(
+        RewrittenConfig::parse_file(&args, &file).unwrap().timeout,
+        5
+    );
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "[Defaults]\ntimeout=5\n\n[Metadata]\nversion=2\n"
+    );
+
+    // The migrated values are used even if the file can't be rewritten,
+    // like when the name of its temporary file would be too long.
+    let file = TempFile::new(&format!("versions_{}.ini", "x".repeat(237)));
+    fs::write(&file, "[Defaults]\ntimeout = 6\n").unwrap();
+    let notices = Arc::new(Mutex::new(Vec::new()));
+    let notices_hook = Arc::clone(&notices);
+    diagnostics::set_hook(move |d| notices_hook.lock().unwrap().push(d.clone()));
+    assert_eq!(
+        RewrittenConfig::parse_file(&args, &file).unwrap().timeout,
+        6
+    );
+    diagnostics::reset_hook();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "[Defaults]\ntimeout = 6\n"
+    );
+    let notices = notices.lock().unwrap();
+    match &notices[..] {
+        [Diagnostic::Migrated { .. }, Diagnostic::RewriteFailed { path, .. }] => {
+            assert_eq!(path, &file.0)
+        }
+        other => panic!("unexpected diagnostics: {:?}", other),
+    }
+}
+
+/// The unknown keys and sections in the config file are rejected or
+/// reported, with suggestions for typos.
+#[test]
+fn unknown_keys() {
+    use std::sync::{Arc, Mutex};
+    use structconf::diagnostics::{self, Diagnostic};
+    use structconf::UnknownKey;
+
+    let file = TempFile::new("unknown_keys.ini");
+    let token_file = TempFile::new("unknown_keys_token.txt");
+    fs::write(&token_file, "abcdef").unwrap();
+    fs::write(
+        &file,
+        format!(
+            "[Defaults]\naudiosync_calibration = 1\noffset = 2\n\
+             old_token_file = {}\n\
+             [Network]\nhost = example.com\n[Commands]\nanything = goes\n",
+            &*token_file
+        ),
+    )
+    .unwrap();
+    let args = StrictConfig::parse_args_from(clap::App::new("test"), vec!["test"]);
+    let _lock = HOOK_LOCK.lock().unwrap();
+    diagnostics::set_hook(|_| {});
+    let conf = StrictConfig::parse_file(&args, &file).unwrap();
+    diagnostics::reset_hook();
+    assert_eq!(conf.delay, 2);
+    assert_eq!(conf.commands.len(), 1);
+    assert_eq!(conf.token.as_deref(), Some("abcdef"));
+
+    fs::write(
+        &file,
+        "top = 1\n[Defaults]\naudiosync_calibraton = 50\nunrelated = 1\n\
+         [Netwrk]\nhost = example.com\n",
+    )
+    .unwrap();
+    let unknown = |section: Option<&str>, key: Option<&str>, suggestion: Option<&str>| UnknownKey {
+        path: file.to_string(),
+        section: section.map(str::to_string),
+        key: key.map(str::to_string),
+        suggestion: suggestion.map(str::to_string),
+    };
+    let expected = [
+        unknown(None, Some("top"), None),
+        unknown(
+            Some("Defaults"),
+            Some("audiosync_calibraton"),
+            Some("audiosync_calibration"),
+        ),
+        unknown(Some("Defaults"), Some("unrelated"), None),
+        unknown(Some("Netwrk"), None, Some("Network")),
+    ];
+    match StrictConfig::parse_file(&args, &file) {
+        Err(Error::Multiple(errors)) => {
+            let errors = errors
+                .into_iter()
+                .map(|e| match e {
+                    Error::Unknown(unknown) => unknown,
+                    e => panic!("unexpected error: {}", e),
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(errors, expected);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(
+        expected[1].to_string(),
+        format!(
+            "Unknown key `audiosync_calibraton` in section `[Defaults]` of {}, \
+             did you mean `audiosync_calibration`?",
+            &*file
+        )
+    );
+    assert_eq!(
+        expected[3].to_string(),
+        format!(
+            "Unknown section `[Netwrk]` in {}, did you mean `[Network]`?",
+            &*file
+        )
+    );
+    let (conf, errors) = StrictConfig::parse_file_all_errors(&args, &file);
+    assert_eq!(conf.unwrap().audiosync_calibration, 0);
+    assert_eq!(errors.len(), 1);
+
+    // Only a warning is emitted in the lenient mode.
+    let notices = Arc::new(Mutex::new(Vec::new()));
+    let notices_hook = Arc::clone(&notices);
+    diagnostics::set_hook(move |d| notices_hook.lock().unwrap().push(d.clone()));
+    fs::write(&file, "[Defaults]\ntimeout = 3\ntimeotu = 4\n").unwrap();
+    let args = LenientConfig::parse_args_from(clap::App::new("test"), vec!["test"]);
+    assert_eq!(LenientConfig::parse_file(&args, &file).unwrap().timeout, 3);
+    diagnostics::reset_hook();
+    assert_eq!(
+        *notices.lock().unwrap(),
+        [Diagnostic::Unknown(unknown(
+            Some("Defaults"),
+            Some("timeotu"),
+            Some("timeout")
+        ))]
+    );
+}
+
+/// Required fields fail if they aren't set anywhere, instead of taking their
+/// default value.
+#[test]
+fn required() {
+    let file = TempFile::new("required.ini");
+    fs::write(&file, "[Defaults]\ntimeout = 3\n").unwrap();
+    let args = RequiredConfig::parse_args_from(clap::App::new("test"), vec!["test"]);
+    match RequiredConfig::parse_file(&args, &file) {
+        Err(Error::Missing {
+            field,
+            where_to_set,
+        }) => {
+            assert_eq!(field, "api_key");
+            assert_eq!(
+                where_to_set,
+                [
+                    "`--api-key`",
+                    "`STRUCTCONF_REQUIRED_KEY` in the environment",
+                    "`api_key` in section `[Auth]` of the config file",
+                ]
+            );
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // The rest of fields are still checked, but the struct can't be
+    // initialized without the required ones.
+    let (conf, errors) = RequiredConfig::parse_file_all_errors(&args, &file);
+    assert!(conf.is_none());
+    assert_eq!(
+        errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        [
+            "`api_key` is required, but it wasn't set. It can be set with `--api-key`, \
+             `STRUCTCONF_REQUIRED_KEY` in the environment or `api_key` in section \
+             `[Auth]` of the config file",
+            "`port` is required, but it wasn't set. It can be set with `--port`",
+            "`token` is required, but it wasn't set. It can be set with `--token`, \
+             `--token-file`, `STRUCTCONF_REQUIRED_TOKEN` in the environment, \
+             `STRUCTCONF_REQUIRED_TOKEN_FILE` in the environment, `token` in section \
+             `[Defaults]` of the config file or `token_file` in section `[Defaults]` \
+             of the config file",
+        ]
+    );
+
+    let token_file = TempFile::new("required_token.txt");
+    fs::write(&token_file, "abc\n").unwrap();
+    fs::write(
+        &file,
+        format!(
+            "[Auth]\napi_key = secret\n[Defaults]\ntoken_file = {}\n",
+            &*token_file
+        ),
+    )
+    .unwrap();
+    let args =
+        RequiredConfig::parse_args_from(clap::App::new("test"), vec!["test", "--port", "80"]);
+    assert_eq!(
+        RequiredConfig::parse_file(&args, &file).unwrap(),
+        RequiredConfig {
+            api_key: "secret".to_string(),
+            port: Port(80),
+            timeout: 0,
+            token: "abc".to_string(),
+        }
+    );
+    let (conf, errors) = RequiredConfig::parse_file_all_errors(&args, &file);
+    assert!(errors.is_empty());
+    assert!(conf.is_some());
+}
+
+/// Renamed options keep working with their aliases, which are reported as
+/// deprecated.
+#[test]
+fn aliases() {
+    use std::sync::{Arc, Mutex};
+    use structconf::diagnostics::{self, Diagnostic};
+
+    let file = TempFile::new("aliases.ini");
+    fs::write(&file, "[Defaults]\ntimeout = 500\n").unwrap();
+
+    let _lock = HOOK_LOCK.lock().unwrap();
+    let notices = Arc::new(Mutex::new(Vec::new()));
+    let notices_hook = Arc::clone(&notices);
+    diagnostics::set_hook(move |d| notices_hook.lock().unwrap().push(d.clone()));
+    let parse = |args: Vec<&str>| {
+        let args = AliasConfig::parse_args_from(clap::App::new("test"), args);
+        AliasConfig::parse_file_with_sources(&args, &file).unwrap()
+    };
+
+    let (conf, sources) = parse(vec!["test", "--loud", "--legacy", "yes"]);
+    assert_eq!(
+        conf,
+        AliasConfig {
+            timeout_ms: 500,
+            debug: true,
+            legacy: Some("yes".to_string()),
+        }
+    );
+    let timeout_source = Source::File {
+        path: file.to_string(),
+        section: "Defaults".to_string(),
+        key: Some("timeout".to_string()),
+    };
+    assert_eq!(sources.source_of("timeout_ms"), Some(&timeout_source));
+    let (conf, sources) = parse(vec!["test", "--timeout", "300"]);
+    assert_eq!(conf.timeout_ms, 300);
+    assert_eq!(
+        sources.source_of("timeout_ms"),
+        Some(&Source::Argument {
+            name: "--timeout".to_string()
+        })
+    );
+    diagnostics::reset_hook();
+
+    assert_eq!(
+        notices.lock().unwrap()[..3],
+        [
+            Diagnostic::Deprecated {
+                field: "timeout_ms".to_string(),
+                source: timeout_source,
+                note: Some("use `timeout_ms` instead".to_string()),
+            },
+            Diagnostic::Deprecated {
+                field: "debug".to_string(),
+                source: Source::Argument {
+                    name: "--loud".to_string()
+                },
+                note: None,
+            },
+            Diagnostic::Deprecated {
+                field: "legacy".to_string(),
+                source: Source::Argument {
+                    name: "--legacy".to_string()
+                },
+                note: Some("it has no effect".to_string()),
+            },
+        ]
+    );
+    assert_eq!(
+        notices.lock().unwrap()[0].to_string(),
+        format!(
+            "The key `timeout` in section `[Defaults]` of `{}` for `timeout_ms` is \
+             deprecated: use `timeout_ms` instead",
+            &*file
+        )
+    );
+
+    // The current name takes priority, and it's the only one written.
+    fs::write(&file, "[Defaults]\ntimeout = 500\ntimeout_ms = 200\n").unwrap();
+    let (conf, _) = parse(vec!["test"]);
+    assert_eq!(conf.timeout_ms, 200);
+    conf.write_file(&file).unwrap();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "[Defaults]\ntimeout_ms=200\ndebug=false\n"
+    );
+    assert_eq!(AliasConfig::options().len(), 3);
+}
+
+/// Generic structs may be used with any type that can be parsed.
+#[test]
+fn generics() {
+    let file = TempFile::new("generics.ini");
+    fs::write(&file, "[Defaults]\nvalue = 2.5\n").unwrap();
+
+    let app = clap::App::new("test");
+    let args = vec!["test", "--values", "1.5"];
+    let args = GenericConfig::<f64, Sqlite>::parse_args_from(app, args);
+    let conf = GenericConfig::<f64, Sqlite>::parse_file(&args, &file).unwrap();
+    assert_eq!(
+        conf,
+        GenericConfig {
+            value: 2.5,
+            values: vec![1.5],
+            backend: std::marker::PhantomData,
+        }
+    );
+    assert_eq!(Sqlite::NAME, "sqlite");
+
+    // The marker isn't an option.
+    let fields = GenericConfig::<MyEnum, Sqlite>::options()
+        .into_iter()
+        .filter(|opt| opt.section.is_some())
+        .map(|opt| opt.field)
+        .collect::<Vec<_>>();
+    assert_eq!(fields, vec!["value".to_string(), "values".to_string()]);
+}
+
+/// All the errors can be collected instead of failing on the first one.
+#[test]
+fn all_errors() {
+    let file = TempFile::new("all_errors.ini");
+
+    let mut f = File::create(&file).unwrap();
+    f.write_all(b"[Defaults]\nport = abc\nhost = \nworkers = 2,4")
+        .unwrap();
+
+    let app = clap::App::new("test");
+    let args = ValidationConfig::parse_args_from(app, vec!["test", "--workers", "3"]);
+    let (conf, errors) = ValidationConfig::parse_file_all_errors(&args, &file);
+    let conf = conf.unwrap();
+    assert_eq!(errors.len(), 3);
+    match &errors[0] {
+        Error::Parse(e) => {
+            assert_eq!(e.field, "port");
+            assert_eq!(e.line, Some(2));
+        }
+        e => panic!("parse error not returned: {:?}", e),
+    }
+    match &errors[1] {
+        Error::Validation { field, .. } => assert_eq!(field, "host"),
+        e => panic!("validation error not returned: {:?}", e),
+    }
+    match &errors[2] {
+        Error::Validation { field, .. } => assert_eq!(field, "workers"),
+        e => panic!("validation error not returned: {:?}", e),
+    }
+
+    // The failing fields take their default values.
+    assert_eq!(conf.port, 8080);
+    assert_eq!(conf.host, None);
+    assert_eq!(conf.workers, Vec::<u32>::new());
+    match Error::from_all(errors) {
+        Err(Error::Multiple(errors)) => assert_eq!(errors.len(), 3),
+        s => panic!("multiple errors not returned: {:?}", s),
+    }
+
+    // Without errors, the values are the same as with `parse_file`.
+    let mut f = File::create(&file).unwrap();
+    f.write_all(b"[Defaults]\nport = 1234").unwrap();
+    let app = clap::App::new("test");
+    let args = ValidationConfig::parse_args_from(app, vec!["test"]);
+    let (conf, errors) = ValidationConfig::parse_file_all_errors(&args, &file);
+    assert!(errors.is_empty());
+    assert_eq!(
+        conf,
+        Some(ValidationConfig::parse_file(&args, &file).unwrap())
+    );
+}
+
+#[derive(Debug, PartialEq, StructConf)]
+struct FormatConfig {
+    #[conf(no_short)]
+    name: String,
+    #[conf(no_short)]
+    number: i32,
+    #[conf(no_short)]
+    floating: f64,
+    #[conf(no_short, section = "Server")]
+    enabled: bool,
+    #[conf(no_short, section = "Server.Limits")]
+    count: MyEnum,
+    #[conf(no_short)]
+    numeric_str: String,
+    #[conf(no_short)]
+    tags: Vec<String>,
+}
+
+/// Writes a config into the file and makes sure it's read back the same.
+/// The written file is returned for further checks.
+#[allow(dead_code)]
+fn format_roundtrip(path: &str) -> String {
+    let file = TempFile::new(path);
+
+    let app = clap::App::new("test");
+    let args = FormatConfig::parse_args_from(app, vec!["test"]);
+    let mut conf = FormatConfig::parse_file(&args, &file).unwrap();
+    conf.name = String::from("some name");
+    conf.number = 1234;
+    conf.floating = 12.5;
+    conf.enabled = true;
+    conf.count = MyEnum::Three;
+    conf.numeric_str = String::from("007");
+    conf.tags = vec![String::from("first"), String::from("second")];
+    conf.write_file(&file).unwrap();
+
+    let read = FormatConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(read, conf);
+
+    fs::read_to_string(&file).unwrap()
+}
+
+/// Unknown extensions are read as INI.
+#[test]
+fn format_ini() {
+    let contents = format_roundtrip("format.conf");
+    assert!(contents.contains("[Server.Limits]"));
+}
+
+/// Formats whose feature isn't enabled can't be used.
+#[cfg(not(feature = "toml"))]
+#[test]
+fn format_disabled() {
+    let file = TempFile::new("format_disabled.toml");
+    File::create(&file).unwrap();
+
+    let app = clap::App::new("test");
+    let args = FormatConfig::parse_args_from(app, vec!["test"]);
+    match FormatConfig::parse_file(&args, &file) {
+        Err(Error::Format(_)) => {}
+        s => panic!("format error not returned: {:?}", s),
+    }
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn format_toml() {
+    let contents = format_roundtrip("format.toml");
+    assert!(contents.contains("[Server.Limits]"));
+    assert!(contents.contains("number = 1234"));
+    assert!(contents.contains("numeric_str = \"007\""));
+    assert!(contents.contains("tags = [\"first\", \"second\"]"));
+
+    let file = TempFile::new("format_read.toml");
+    let mut f = File::create(&file).unwrap();
+    f.write_all(
+        b"[Defaults]
+number = 10
+floating = 1.5
+
+[Server]
+enabled = true
+Limits = { count = \"Two\" }",
+    )
+    .unwrap();
+
+    let app = clap::App::new("test");
+    let args = FormatConfig::parse_args_from(app, vec!["test"]);
+    let conf = FormatConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(conf.number, 10);
+    assert_eq!(conf.floating, 1.5);
+    assert_eq!(conf.enabled, true);
+    assert_eq!(conf.count, MyEnum::Two);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn format_json() {
+    let contents = format_roundtrip("format.json");
+    assert!(contents.contains("\"number\": 1234"));
+
+    let file = TempFile::new("format_read.json");
+    let mut f = File::create(&file).unwrap();
+    f.write_all(br#"{"Defaults": {"number": 10, "name": null}, "Server": {"enabled": true}}"#)
+        .unwrap();
+
+    let app = clap::App::new("test");
+    let args = FormatConfig::parse_args_from(app, vec!["test"]);
+    let conf = FormatConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(conf.number, 10);
+    assert_eq!(conf.name, "");
+    assert_eq!(conf.enabled, true);
+}
+
+/// Templates are only available for INI files, so the rest are created
+/// empty instead.
+#[cfg(feature = "json")]
+#[test]
+fn missing_template_json() {
+    use std::sync::{Arc, Mutex};
+    use structconf::diagnostics::{self, Diagnostic};
+
+    let file = TempFile::new("missing_template.json");
+    let _lock = HOOK_LOCK.lock().unwrap();
+    let notices = Arc::new(Mutex::new(Vec::new()));
+    let notices_hook = Arc::clone(&notices);
+    diagnostics::set_hook(move |d| notices_hook.lock().unwrap().push(d.clone()));
+    let app = clap::App::new("test");
+    let args = TemplateConfig::parse_args_from(app, vec!["test"]);
+    let conf = TemplateConfig::parse_file(&args, &file).unwrap();
+    diagnostics::reset_hook();
+    assert_eq!(conf.timeout, 30);
+    assert_eq!(fs::read_to_string(&file).unwrap(), "");
+
+    let notices = notices.lock().unwrap();
+    let notices = notices
+        .iter()
+        .filter(|d| d.to_string().contains(&*file))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        notices,
+        [
+            &Diagnostic::NoTemplate {
+                path: file.to_string(),
+            },
+            &Diagnostic::CreatedFile {
+                path: file.to_string(),
+            },
+        ]
+    );
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn format_yaml() {
+    let contents = format_roundtrip("format.yaml");
+    assert!(contents.contains("number: 1234"));
+
+    let file = TempFile::new("format_read.yml");
+    let mut f = File::create(&file).unwrap();
+    f.write_all(
+        b"Defaults:
+  number: 10
+Server:
+  enabled: true
+  Limits:
+    count: Two",
+    )
+    .unwrap();
+
+    let app = clap::App::new("test");
+    let args = FormatConfig::parse_args_from(app, vec!["test"]);
+    let conf = FormatConfig::parse_file(&args, &file).unwrap();
+    assert_eq!(conf.number, 10);
+    assert_eq!(conf.enabled, true);
+    assert_eq!(conf.count, MyEnum::Two);
+}